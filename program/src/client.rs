@@ -0,0 +1,287 @@
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program,
+    sysvar::{self, slot_hashes},
+};
+
+use crate::error::LotteryError;
+use crate::instructions::{LotteryInstructions, StartLotteryArgs};
+use crate::state::{LotteryAccount, LotteryEntry, ParticipantBet, RandomnessMode, StorageMode};
+use crate::utils::load_lottery;
+
+/// PDA of the lottery, derived from its authority and lottery id
+pub fn get_lottery_address(program_id: &Pubkey, authority: &Pubkey, lottery_id: u64) -> Pubkey {
+    LotteryAccount::get_lottery_pubkey(program_id, authority, lottery_id).0
+}
+
+/// token vault PDA of the lottery in SPL token
+pub fn get_vault_address(program_id: &Pubkey, lottery: &Pubkey) -> Pubkey {
+    LotteryAccount::get_vault_pubkey(program_id, lottery).0
+}
+
+/// entry PDA of participant in the lottery in Entries mode
+pub fn get_entry_address(program_id: &Pubkey, lottery: &Pubkey, participant: &Pubkey) -> Pubkey {
+    LotteryEntry::get_entry_pubkey(program_id, lottery, participant).0
+}
+
+/// decoding lottery account fetched over RPC, returns header and participants, who have already donated
+pub fn decode_lottery(data: &[u8]) -> Result<(LotteryAccount, Vec<ParticipantBet>), ProgramError> {
+    // RPC data isn't guaranteed to be aligned, load_lottery casts it in place
+    let mut aligned = vec![0u64; data.len().div_ceil(8)];
+    let aligned_bytes: &mut [u8] = bytemuck::cast_slice_mut(&mut aligned);
+    aligned_bytes[..data.len()].copy_from_slice(data);
+
+    let (lottery_account, participants) = load_lottery(&aligned_bytes[..data.len()])?;
+
+    Ok((*lottery_account, participants.to_vec()))
+}
+
+/// decoding participant's entry fetched over RPC
+pub fn decode_entry(data: &[u8]) -> Result<LotteryEntry, ProgramError> {
+    bytemuck::try_pod_read_unaligned(data).map_err(|_| LotteryError::InvalidEntry.into())
+}
+
+/// accounts, that move tokens of a lottery in SPL token
+fn token_metas(program_id: &Pubkey, lottery: &Pubkey) -> [AccountMeta; 2] {
+    [
+        AccountMeta::new(get_vault_address(program_id, lottery), false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ]
+}
+
+/// starting a new lottery or a new round of the existing one
+/// lottery PDA is derived from its creator, so it stays the same after authority transfer
+pub fn start_lottery(program_id: &Pubkey, authority: &Pubkey, lottery: &Pubkey, args: StartLotteryArgs) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*authority, true),
+        AccountMeta::new(*lottery, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+
+    if let Some(mint) = args.mint {
+        accounts.push(AccountMeta::new(get_vault_address(program_id, lottery), false));
+        accounts.push(AccountMeta::new_readonly(mint, false));
+        accounts.push(AccountMeta::new_readonly(spl_token::id(), false));
+    }
+
+    Instruction::new_with_bytes(*program_id, &LotteryInstructions::StartLottery(args).pack(), accounts)
+}
+
+/// betting lamports or tokens, participant's token account is passed for lotteries in SPL token
+pub fn donate(
+    program_id: &Pubkey,
+    participant: &Pubkey,
+    lottery: &Pubkey,
+    amount: u64,
+    token_account: Option<&Pubkey>,
+    storage_mode: StorageMode,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*participant, true),
+        AccountMeta::new(*lottery, false),
+    ];
+
+    match token_account {
+        Some(token_account) => {
+            accounts.extend(token_metas(program_id, lottery));
+            accounts.push(AccountMeta::new(*token_account, false));
+        }
+        None => accounts.push(AccountMeta::new_readonly(system_program::id(), false)),
+    }
+
+    if storage_mode == StorageMode::Entries {
+        accounts.push(AccountMeta::new(get_entry_address(program_id, lottery, participant), false));
+        accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+    }
+
+    Instruction::new_with_bytes(*program_id, &LotteryInstructions::DonateInstruction(amount).pack(), accounts)
+}
+
+/// launching the draw, Oracle mode reads Instructions sysvar, other modes read SlotHashes
+pub fn launch_lottery(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    lottery: &Pubkey,
+    randomness_mode: RandomnessMode,
+    reveal: Option<[u8; 32]>,
+) -> Instruction {
+    let sysvar_id = match randomness_mode {
+        RandomnessMode::Oracle => sysvar::instructions::id(),
+        RandomnessMode::SlotHashes | RandomnessMode::CommitReveal => slot_hashes::id(),
+    };
+
+    Instruction::new_with_bytes(*program_id, &LotteryInstructions::LaunchLottery(reveal).pack(), vec![
+        AccountMeta::new_readonly(*authority, true),
+        AccountMeta::new(*lottery, false),
+        AccountMeta::new_readonly(sysvar_id, false),
+    ])
+}
+
+/// paying fee and prizes, token accounts of treasury and winners are passed for lotteries in SPL token
+/// winners go in tier order
+pub fn complete_lottery(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    lottery: &Pubkey,
+    treasury: &Pubkey,
+    winners: &[Pubkey],
+    token_lottery: bool,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*authority, true),
+        AccountMeta::new(*lottery, false),
+        AccountMeta::new(*treasury, false),
+    ];
+
+    if token_lottery {
+        accounts.extend(token_metas(program_id, lottery));
+    }
+    accounts.extend(winners.iter().map(|winner| AccountMeta::new(*winner, false)));
+
+    Instruction::new_with_bytes(*program_id, &LotteryInstructions::CompleteLottery.pack(), accounts)
+}
+
+/// authority's instructions, that take authority and lottery accounts only
+fn authority_instruction(program_id: &Pubkey, authority: &Pubkey, lottery: &Pubkey, instruction: LotteryInstructions) -> Instruction {
+    Instruction::new_with_bytes(*program_id, &instruction.pack(), vec![
+        AccountMeta::new_readonly(*authority, true),
+        AccountMeta::new(*lottery, false),
+    ])
+}
+
+pub fn set_treasury(program_id: &Pubkey, authority: &Pubkey, lottery: &Pubkey, treasury: Pubkey) -> Instruction {
+    authority_instruction(program_id, authority, lottery, LotteryInstructions::SetTreasury(treasury))
+}
+
+pub fn propose_authority(program_id: &Pubkey, authority: &Pubkey, lottery: &Pubkey, new_authority: Pubkey) -> Instruction {
+    authority_instruction(program_id, authority, lottery, LotteryInstructions::ProposeAuthority(new_authority))
+}
+
+/// signed by the proposed authority
+pub fn accept_authority(program_id: &Pubkey, new_authority: &Pubkey, lottery: &Pubkey) -> Instruction {
+    authority_instruction(program_id, new_authority, lottery, LotteryInstructions::AcceptAuthority)
+}
+
+pub fn cancel_authority_proposal(program_id: &Pubkey, authority: &Pubkey, lottery: &Pubkey) -> Instruction {
+    authority_instruction(program_id, authority, lottery, LotteryInstructions::CancelAuthorityProposal)
+}
+
+/// signer can be anyone, if operator of commit-reveal lottery missed reveal deadline
+pub fn cancel_lottery(program_id: &Pubkey, signer: &Pubkey, lottery: &Pubkey) -> Instruction {
+    authority_instruction(program_id, signer, lottery, LotteryInstructions::CancelLottery)
+}
+
+/// refunding participants of cancelled lottery, their token accounts go in the same order for lotteries in SPL token
+pub fn refund_participants(
+    program_id: &Pubkey,
+    lottery: &Pubkey,
+    participants: &[Pubkey],
+    token_accounts: Option<&[Pubkey]>,
+    storage_mode: StorageMode,
+) -> Instruction {
+    let mut accounts = vec![AccountMeta::new(*lottery, false)];
+
+    if token_accounts.is_some() {
+        accounts.extend(token_metas(program_id, lottery));
+    }
+
+    for (index, participant) in participants.iter().enumerate() {
+        if storage_mode == StorageMode::Entries {
+            accounts.push(AccountMeta::new(get_entry_address(program_id, lottery, participant), false));
+        }
+
+        let destination = token_accounts.map_or(participant, |token_accounts| &token_accounts[index]);
+        accounts.push(AccountMeta::new(*destination, false));
+    }
+
+    Instruction::new_with_bytes(*program_id, &LotteryInstructions::RefundParticipants.pack(), accounts)
+}
+
+/// returning participant's bet, participant's token account is passed for lotteries in SPL token
+pub fn withdraw_bet(
+    program_id: &Pubkey,
+    participant: &Pubkey,
+    lottery: &Pubkey,
+    token_account: Option<&Pubkey>,
+    storage_mode: StorageMode,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*participant, true),
+        AccountMeta::new(*lottery, false),
+    ];
+
+    if let Some(token_account) = token_account {
+        accounts.extend(token_metas(program_id, lottery));
+        accounts.push(AccountMeta::new(*token_account, false));
+    }
+
+    if storage_mode == StorageMode::Entries {
+        accounts.push(AccountMeta::new(get_entry_address(program_id, lottery, participant), false));
+    }
+
+    Instruction::new_with_bytes(*program_id, &LotteryInstructions::WithdrawBet.pack(), accounts)
+}
+
+/// resolving winners of a lottery in Entries mode, entries go in draw order
+pub fn resolve_winners(program_id: &Pubkey, lottery: &Pubkey, entries: &[Pubkey]) -> Instruction {
+    let mut accounts = vec![AccountMeta::new(*lottery, false)];
+    accounts.extend(entries.iter().map(|entry| AccountMeta::new_readonly(*entry, false)));
+
+    Instruction::new_with_bytes(*program_id, &LotteryInstructions::ResolveWinners.pack(), accounts)
+}
+
+/// one instruction adds up to about 182 slots, send several of them to grow more
+pub fn expand_lottery(program_id: &Pubkey, authority: &Pubkey, lottery: &Pubkey, max_participants: u32) -> Instruction {
+    Instruction::new_with_bytes(*program_id, &LotteryInstructions::ExpandLottery(max_participants).pack(), vec![
+        AccountMeta::new(*authority, true),
+        AccountMeta::new(*lottery, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ])
+}
+
+/// closing finished lottery, destination is the authority or the treasury
+/// lotteries in SPL token pass destination's token account, tokens left in the vault are swept there and the vault is closed
+pub fn close_lottery(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    lottery: &Pubkey,
+    destination: &Pubkey,
+    destination_token_account: Option<&Pubkey>,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*authority, true),
+        AccountMeta::new(*lottery, false),
+        AccountMeta::new(*destination, false),
+    ];
+
+    if let Some(destination_token_account) = destination_token_account {
+        accounts.extend(token_metas(program_id, lottery));
+        accounts.push(AccountMeta::new(*destination_token_account, false));
+    }
+
+    Instruction::new_with_bytes(*program_id, &LotteryInstructions::CloseLottery.pack(), accounts)
+}
+
+/// migrating v1 lottery account, lotteries in Entries mode pass all their entries, that haven't been closed
+pub fn migrate_lottery(program_id: &Pubkey, authority: &Pubkey, lottery: &Pubkey, entries: &[Pubkey]) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*authority, true),
+        AccountMeta::new(*lottery, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    accounts.extend(entries.iter().map(|entry| AccountMeta::new_readonly(*entry, false)));
+
+    Instruction::new_with_bytes(*program_id, &LotteryInstructions::MigrateLottery.pack(), accounts)
+}
+
+/// closing participant's entry after its bet is settled, anyone can send it, rent goes back to participant
+pub fn close_entry(program_id: &Pubkey, lottery: &Pubkey, participant: &Pubkey) -> Instruction {
+    Instruction::new_with_bytes(*program_id, &LotteryInstructions::CloseEntry.pack(), vec![
+        AccountMeta::new(*lottery, false),
+        AccountMeta::new(get_entry_address(program_id, lottery, participant), false),
+        AccountMeta::new(*participant, false),
+    ])
+}
@@ -1,12 +1,173 @@
+use num_derive::FromPrimitive;
+use solana_program::{
+    decode_error::DecodeError,
+    msg,
+    program_error::{PrintProgramError, ProgramError},
+};
 use thiserror::Error;
 
-#[derive(Error, Debug, Copy, Clone)]
-pub enum EscrowError {
+#[derive(Error, Debug, Copy, Clone, PartialEq, Eq, FromPrimitive)]
+pub enum LotteryError {
     /// Invalid instruction
     #[error("Invalid Instruction")]
     InvalidInstruction,
+    /// Lottery reached max amount of participants or doesn't accept bets anymore
+    #[error("Lottery is full")]
+    LotteryIsFull,
+    /// Instruction was called in the wrong lottery lifecycle state
+    #[error("Wrong lottery lifecycle state")]
+    WrongLifecycleState,
+    /// Nobody has donated to the lottery
+    #[error("Lottery has no donations")]
+    NoDonations,
+    /// Winner hasn't been chosen yet
+    #[error("Winner is not chosen")]
+    WinnerNotChosen,
+    /// Passed winner account doesn't match the chosen one
+    #[error("Winner account mismatch")]
+    WinnerMismatch,
+    /// Passed lottery account isn't the PDA derived from authority and lottery id
+    #[error("Invalid lottery PDA")]
+    InvalidLotteryPda,
+    /// Lottery account isn't writable or isn't owned by the program
+    #[error("Invalid lottery account")]
+    InvalidLotteryAccount,
+    /// Participant account can't be debited
+    #[error("Invalid participant account")]
+    InvalidParticipantAccount,
+    /// Lottery account doesn't hold enough lamports for the payout
+    #[error("Insufficient lottery funds")]
+    InsufficientFunds,
+    /// Passed account isn't SlotHashes sysvar or its data is malformed
+    #[error("Invalid SlotHashes sysvar account")]
+    InvalidSlotHashesAccount,
+    /// Commitment is missing or its reveal deadline has already passed at start
+    #[error("Invalid commitment")]
+    InvalidCommitment,
+    /// Revealed seed doesn't match the commitment
+    #[error("Reveal doesn't match the commitment")]
+    InvalidReveal,
+    /// Seed wasn't revealed before the deadline
+    #[error("Reveal deadline has passed")]
+    RevealDeadlinePassed,
+    /// Both commitment and oracle are set for the lottery
+    #[error("Conflicting randomness modes")]
+    ConflictingRandomnessModes,
+    /// Passed account isn't Instructions sysvar
+    #[error("Invalid Instructions sysvar account")]
+    InvalidInstructionsSysvar,
+    /// Previous instruction isn't a single signature ed25519 verification with inline data
+    #[error("Invalid oracle signature instruction")]
+    InvalidOracleInstruction,
+    /// Draw message is signed by another key
+    #[error("Oracle signer mismatch")]
+    OracleSignerMismatch,
+    /// Signed message isn't lottery pubkey, current round and a recent draw slot
+    #[error("Invalid oracle message")]
+    InvalidOracleMessage,
+    /// Prize tiers are empty, too many, contain zero or don't add up to 100 percent
+    #[error("Invalid prize tiers")]
+    InvalidPrizeTiers,
+    /// Lottery has fewer participants than prize tiers
+    #[error("Not enough participants")]
+    NotEnoughParticipants,
+    /// Fee is greater than MAX_FEE_BPS
+    #[error("Invalid fee")]
+    InvalidFee,
+    /// Arithmetic operation overflowed
+    #[error("Arithmetic overflow")]
+    ArithmeticOverflow,
+    /// Signer isn't the authority of the lottery
+    #[error("Invalid lottery authority")]
+    InvalidAuthority,
+    /// Treasury is not set or passed treasury account doesn't match the configured one
+    #[error("Invalid treasury")]
+    InvalidTreasury,
+    /// There is no pending authority proposal or signer isn't the proposed authority
+    #[error("Invalid pending authority")]
+    InvalidPendingAuthority,
+    /// Cancelled lottery still has participants, who weren't refunded
+    #[error("Refunds are pending")]
+    RefundsPending,
+    /// End timestamp is in the past or reveal deadline is before it
+    #[error("Invalid lottery end")]
+    InvalidLotteryEnd,
+    /// Lottery end timestamp has passed, bets can't be changed anymore
+    #[error("Lottery has ended")]
+    LotteryEnded,
+    /// Lottery end timestamp hasn't passed yet, draw can't happen
+    #[error("Lottery hasn't ended yet")]
+    LotteryNotEnded,
+    /// Both ticket price and min/max bet are set or min bet is greater than max bet
+    #[error("Invalid bet limits")]
+    InvalidBetLimits,
+    /// Bet is zero, not a whole number of tickets or out of min/max range
+    #[error("Invalid bet amount")]
+    InvalidBetAmount,
+    /// Passed mint doesn't match the lottery one or isn't owned by token program
+    #[error("Invalid mint")]
+    InvalidMint,
+    /// Passed vault isn't the token account PDA of the lottery or holds another mint
+    #[error("Invalid token vault")]
+    InvalidVault,
+    /// Token account isn't owned by token program, holds another mint or belongs to someone else
+    #[error("Invalid token account")]
+    InvalidTokenAccount,
+    /// Entry isn't the PDA of the lottery and participant or belongs to another lottery round
+    #[error("Invalid lottery entry")]
+    InvalidEntry,
+    /// Passed entry doesn't cover the drawn number of the cumulative index
+    #[error("Entry doesn't match the drawn number")]
+    EntryMismatch,
+    /// Others have joined after participant's entry, so its range of the cumulative index can't change anymore
+    #[error("Entry range is closed")]
+    EntryRangeClosed,
+    /// Lottery still holds bets, which haven't been paid out or returned, so it can't be closed
+    #[error("Funds are pending")]
+    FundsPending,
+    /// Lottery account has unknown version or an old one, which has to be migrated with MigrateLottery
+    #[error("Unsupported lottery account version")]
+    UnsupportedVersion,
+    /// Stored participants count exceeds max participants or slots of the lottery account
+    #[error("Invalid participants count")]
+    InvalidParticipantsCount,
+    /// Entry still holds a bet of the current round, which can be paid out or refunded
+    #[error("Entry is in use")]
+    EntryInUse,
+    /// Entries of the lottery haven't been closed yet, so it can't be closed
+    #[error("Entries are open")]
+    EntriesOpen,
+    /// Lottery in Entries mode keeps bets in entry PDAs and has no participant slots to grow
+    #[error("Lottery has no participant slots")]
+    NoParticipantSlots,
+    /// Max participants can be raised only, used slots can't be cut off
+    #[error("Max participants can't be decreased")]
+    MaxParticipantsDecreased,
+    /// Account can grow by MAX_PERMITTED_DATA_INCREASE bytes per instruction only
+    #[error("Account growth limit exceeded")]
+    GrowthLimitExceeded,
+    /// Rent of closed lottery can go to its authority or treasury only
+    #[error("Invalid destination account")]
+    InvalidDestination,
 }
 
-pub fn create_u32_error(string: &str) -> u32 {
-    string.to_string().parse::<u32>().unwrap()
-}
\ No newline at end of file
+impl From<LotteryError> for ProgramError {
+    fn from(e: LotteryError) -> Self {
+        ProgramError::Custom(e as u32)
+    }
+}
+
+impl<T> DecodeError<T> for LotteryError {
+    fn type_of() -> &'static str {
+        "LotteryError"
+    }
+}
+
+impl PrintProgramError for LotteryError {
+    fn print<E>(&self)
+    where
+        E: 'static + std::error::Error + DecodeError<E> + PrintProgramError + num_traits::FromPrimitive,
+    {
+        msg!("Lottery error: {}", self);
+    }
+}
@@ -4,105 +4,274 @@ use solana_program::{
     entrypoint::ProgramResult,
     program_error::ProgramError,
     msg,
-    program::{invoke, invoke_signed},
+    program::invoke,
     pubkey::Pubkey,
     system_instruction::transfer, 
-    sysvar::{rent::Rent, Sysvar},
-    system_instruction
+    sysvar::{clock::Clock, rent::Rent, Sysvar},
+    hash::hashv
 };
-use crate::state::{LotteryState, LOTTERY_SEED, LotteryAccount, DEFAULT_WINNER_KEY};
-use std::collections::HashMap;
+use crate::error::LotteryError;
+use crate::state::{LotteryState, LOTTERY_SEED, LOTTERY_VERSION, LOTTERY_V1_HEADER_SIZE, LotteryAccount, LotteryAccountV1, ParticipantBet, RandomnessMode, SelectionMode, StorageMode, DEFAULT_WINNER_KEY, MAX_WINNERS, MAX_FEE_BPS};
 use crate::utils::{
-    check_for_lottery_availability, 
     calculate_lottery_account_size,
-    deserialize,
-    calculate_random_number,
+    expand_lottery_account,
+    grow_lottery_account,
+    init_lottery_account,
+    get_lottery_version,
+    check_lottery_account,
+    check_authority,
+    check_lottery_not_ended,
+    check_bet_limits,
+    check_bet_amount,
+    update_ticket_ranges,
+    next_token_vault,
+    get_token_account_owner,
+    open_token_vault,
+    create_pda_account,
+    transfer_from_lottery,
+    close_token_vault,
+    open_entry,
+    load_entry,
+    close_program_account,
+    load_entry_mut,
+    count_entries,
+    resolve_winner_entries,
+    take_entry_bet,
+    load_lottery,
+    load_lottery_mut,
+    find_participant,
+    insert_participant,
+    remove_participant,
+    select_winners,
+    check_prize_tiers,
+    calculate_fee,
+    get_slot_hashes_entropy,
+    check_reveal,
+    get_oracle_entropy,
     calculate_overall_donations,
     check_lottery_lifecycle
 };
 
 
+/// borsh serialized right after instruction code
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct StartLotteryArgs {
+    pub lottery_id: u64,
+    pub max_participants: u32,
+    pub unix_timestamp: u64,
+    /// unix timestamp, bets are accepted before it and the draw can be launched after it
+    pub lottery_end: u64,
+    /// sha256 hash of operator's 32 bytes secret seed, enables commit-reveal draw when set
+    pub commitment: Option<[u8; 32]>,
+    /// unix timestamp, seed must be revealed before it, ignored without commitment
+    pub reveal_deadline: u64,
+    /// ed25519 key, whose signature of lottery pubkey, round and draw slot decides the winner, enables oracle draw when set
+    pub oracle: Option<Pubkey>,
+    /// uniform or stake-weighted winner selection
+    pub selection_mode: SelectionMode,
+    /// percent of the prize for every winner, e.g. [60, 30, 10], must add up to 100
+    pub prize_tiers: Vec<u8>,
+    /// house fee in basis points, can't be greater than MAX_FEE_BPS
+    pub fee_bps: u16,
+    /// account, that receives collected fees
+    pub treasury: Pubkey,
+    /// lamports per ticket, bets must be a whole number of tickets, 0 disables ticket mode
+    pub ticket_price: u64,
+    /// min lamports per bet, 0 if there is no limit, can't be used with ticket price
+    pub min_bet: u64,
+    /// max lamports per bet, 0 if there is no limit, can't be used with ticket price
+    pub max_bet: u64,
+    /// SPL token of the pot, ticket price and bet limits are in its base units then, lamports are used when not set
+    pub mint: Option<Pubkey>,
+    /// participants are kept in the lottery account or in their own entry PDAs, max participants 0 means no limit for the latter
+    pub storage_mode: StorageMode,
+}
+
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
 pub enum LotteryInstructions {
-    StartLottery(u32, u64), // 0
+    StartLottery(StartLotteryArgs), // 0
     DonateInstruction(u64), // 1
-    LaunchLottery(Vec<u8>), // 2
+    LaunchLottery(Option<[u8; 32]>), // 2
     CompleteLottery, // 3
+    SetTreasury(Pubkey), // 4
+    ProposeAuthority(Pubkey), // 5
+    AcceptAuthority, // 6
+    CancelAuthorityProposal, // 7
+    CancelLottery, // 8
+    RefundParticipants, // 9
+    WithdrawBet, // 10
+    ResolveWinners, // 11
+    ExpandLottery(u32), // 12
+    CloseLottery, // 13
+    MigrateLottery, // 14
+    CloseEntry, // 15
 }
 
 impl LotteryInstructions {
     pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
         msg!("Unpacking instruction data!");
 
-        let (inst_code, rest ) = input.split_first().ok_or(ProgramError::InvalidInstructionData)?;
+        let (inst_code, rest ) = input.split_first().ok_or(LotteryError::InvalidInstruction)?;
 
         msg!("Instruction code: {}", inst_code);
 
-        return match inst_code {
+        match inst_code {
             0 => {
-                if rest.len() != 12 {
-                    return Err(ProgramError::InvalidInstructionData);
-                }
-
                 msg!("Processing start lottery instruction");
 
-                let max_participants: Result<[u8; 4], _> = rest[..4].try_into();
-
-                if max_participants.is_ok() {
-                    msg!("Max_participants: {}", u32::from_le_bytes(max_participants.unwrap()));
-                }
-
-                let unix_timestamp: Result<[u8; 8], _> = rest[4..12].try_into();
+                let args = StartLotteryArgs::try_from_slice(rest)
+                    .map_err(|_| LotteryError::InvalidInstruction)?;
 
-                if unix_timestamp.is_ok() {
-                    msg!("Unix timestamp: {}", u64::from_le_bytes(unix_timestamp.unwrap()));
-                }
-                
-                if max_participants.is_ok() && unix_timestamp.is_ok() {
-                    return Ok(Self::StartLottery(
-                                u32::from_le_bytes(max_participants.unwrap()),
-                                u64::from_le_bytes(unix_timestamp.unwrap()))
-                    );
-                }
+                msg!("Lottery id: {}", args.lottery_id);
+                msg!("Max_participants: {}", args.max_participants);
+                msg!("Unix timestamp: {}", args.unix_timestamp);
+                msg!("Lottery end: {}", args.lottery_end);
 
-                msg!("{}, {}",max_participants.is_ok(), unix_timestamp.is_ok());
-                return Err(ProgramError::InvalidInstructionData);
+                Ok(Self::StartLottery(args))
             },
             1 => {
                 msg!("Processing donate instruction");
 
                 if rest.len() != 8 {
-                    return Err(ProgramError::InvalidInstructionData);
+                    return Err(LotteryError::InvalidInstruction.into());
                 }
 
                 let val: Result<[u8; 8], _> = rest[..8].try_into();
 
-                if val.is_ok() {
-                    let amount = u64::from_le_bytes(val.unwrap());
-                    return Ok(Self::DonateInstruction(amount));
-                } else {
-                    msg!("{}", val.is_ok());
-                    return Err(ProgramError::InvalidInstructionData);
+                match val {
+                    Ok(val) => Ok(Self::DonateInstruction(u64::from_le_bytes(val))),
+                    Err(_) => Err(LotteryError::InvalidInstruction.into())
                 }
             },
             2 => {
-                let hashes = rest.to_vec();
-                Ok(Self::LaunchLottery(hashes))
+                // reveal of operator's seed is passed only for commit-reveal lotteries
+                match rest.len() {
+                    0 => Ok(Self::LaunchLottery(None)),
+                    32 => {
+                        let reveal: [u8; 32] = rest.try_into().map_err(|_| LotteryError::InvalidInstruction)?;
+                        Ok(Self::LaunchLottery(Some(reveal)))
+                    }
+                    _ => Err(LotteryError::InvalidInstruction.into())
+                }
             },
             3 => Ok(Self::CompleteLottery),
-            _ => Err(ProgramError::InvalidInstructionData),
+            4 => {
+                msg!("Processing set treasury instruction");
+
+                let treasury: [u8; 32] = rest.try_into().map_err(|_| LotteryError::InvalidInstruction)?;
+                Ok(Self::SetTreasury(Pubkey::new_from_array(treasury)))
+            },
+            5 => {
+                msg!("Processing propose authority instruction");
+
+                let new_authority: [u8; 32] = rest.try_into().map_err(|_| LotteryError::InvalidInstruction)?;
+                Ok(Self::ProposeAuthority(Pubkey::new_from_array(new_authority)))
+            },
+            6 => Ok(Self::AcceptAuthority),
+            7 => Ok(Self::CancelAuthorityProposal),
+            8 => Ok(Self::CancelLottery),
+            9 => Ok(Self::RefundParticipants),
+            10 => Ok(Self::WithdrawBet),
+            11 => Ok(Self::ResolveWinners),
+            12 => {
+                msg!("Processing expand lottery instruction");
+
+                let max_participants: [u8; 4] = rest.try_into().map_err(|_| LotteryError::InvalidInstruction)?;
+                Ok(Self::ExpandLottery(u32::from_le_bytes(max_participants)))
+            },
+            13 => Ok(Self::CloseLottery),
+            14 => Ok(Self::MigrateLottery),
+            15 => Ok(Self::CloseEntry),
+            _ => Err(LotteryError::InvalidInstruction.into()),
         }
     }
+
+    /// instruction code and data in the layout unpack expects
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        match self {
+            Self::StartLottery(args) => {
+                buf.push(0);
+                // writing into a vec can't fail
+                buf.extend(args.try_to_vec().unwrap());
+            }
+            Self::DonateInstruction(lamports_amount) => {
+                buf.push(1);
+                buf.extend(lamports_amount.to_le_bytes());
+            }
+            Self::LaunchLottery(reveal) => {
+                buf.push(2);
+                if let Some(reveal) = reveal {
+                    buf.extend(reveal);
+                }
+            }
+            Self::CompleteLottery => buf.push(3),
+            Self::SetTreasury(treasury) => {
+                buf.push(4);
+                buf.extend(treasury.to_bytes());
+            }
+            Self::ProposeAuthority(new_authority) => {
+                buf.push(5);
+                buf.extend(new_authority.to_bytes());
+            }
+            Self::AcceptAuthority => buf.push(6),
+            Self::CancelAuthorityProposal => buf.push(7),
+            Self::CancelLottery => buf.push(8),
+            Self::RefundParticipants => buf.push(9),
+            Self::WithdrawBet => buf.push(10),
+            Self::ResolveWinners => buf.push(11),
+            Self::ExpandLottery(max_participants) => {
+                buf.push(12);
+                buf.extend(max_participants.to_le_bytes());
+            }
+            Self::CloseLottery => buf.push(13),
+            Self::MigrateLottery => buf.push(14),
+            Self::CloseEntry => buf.push(15),
+        }
+
+        buf
+    }
 }
 
 /// Accounts expected:
-/// 0. `[signer]` Main account, owner of program
-/// 1. `[writable]` PDA account
+/// 0. `[signer, writable]` Main account, authority of the lottery, pays rent when the account is created or grown
+/// 1. `[writable]` PDA account, derived from the creator of the lottery and lottery id
 /// 2. `[]` Rent sysvar
 /// 3. `[]` System program
-pub fn start_lottery(program_id: &Pubkey, accounts: &[AccountInfo], max_participants: u32, unix_timestamp: u64) -> ProgramResult {
+///
+/// Lottery in Slots mode, restarted with a bigger max participants, is grown the same way as with ExpandLottery,
+/// by up to about 182 slots per start, lotteries, that have to grow more, are expanded with ExpandLottery first
+///
+/// Additional accounts for lotteries in SPL token:
+/// 4. `[writable]` Token vault PDA, derived from the lottery PDA, created on the first start
+/// 5. `[]` Mint of the token
+/// 6. `[]` SPL Token program
+pub fn start_lottery(program_id: &Pubkey, accounts: &[AccountInfo], args: StartLotteryArgs) -> ProgramResult {
     msg!("Executing start lottery instruction!");
 
+    let StartLotteryArgs { lottery_id, max_participants, unix_timestamp, lottery_end, commitment, reveal_deadline, oracle, selection_mode, prize_tiers, fee_bps, treasury, ticket_price, min_bet, max_bet, mint, storage_mode } = args;
+
+    check_prize_tiers(&prize_tiers)?;
+
+    if fee_bps > MAX_FEE_BPS {
+        return Err(LotteryError::InvalidFee.into());
+    }
+
+    if commitment.is_some() && oracle.is_some() {
+        return Err(LotteryError::ConflictingRandomnessModes.into());
+    }
+
+    if treasury == Pubkey::default() {
+        return Err(LotteryError::InvalidTreasury.into());
+    }
+
+    check_bet_limits(ticket_price, min_bet, max_bet)?;
+
+    if lottery_end as i64 <= Clock::get()?.unix_timestamp {
+        return Err(LotteryError::InvalidLotteryEnd.into());
+    }
+
     let accounts_iter = &mut accounts.iter();
 
     let main_acc = next_account_info(accounts_iter)?;
@@ -112,77 +281,157 @@ pub fn start_lottery(program_id: &Pubkey, accounts: &[AccountInfo], max_particip
     }
 
     let pda_acc = next_account_info(accounts_iter)?;
-    // checking pda account
-    if !pda_acc.is_writable && pda_acc.owner != program_id {
-        return Err(ProgramError::IncorrectProgramId);
+    // checking pda account, it's not owned by program until it's created
+    if !pda_acc.is_writable {
+        return Err(LotteryError::InvalidLotteryAccount.into());
     }
 
     let rent_sysvar = next_account_info(accounts_iter)?;
     let system_program = next_account_info(accounts_iter)?;
 
-    let (lottery_pubkey, lottery_bump) = LotteryAccount::get_lottery_pubkey(program_id);
-    if !LotteryAccount::check_pubkey(program_id, pda_acc.key) {
-        return Err(ProgramError::InvalidArgument);
-    }
-
-    msg!("Pda acc data before {:?}", &pda_acc.data.borrow());
-
     // if data is empty, we need to create PDA account first
+    // existing lottery is checked by owner and stored authority instead, it could be handed over since creation
     if pda_acc.data_is_empty() {
+        let (lottery_pubkey, lottery_bump) = LotteryAccount::get_lottery_pubkey(program_id, main_acc.key, lottery_id);
+        if lottery_pubkey != *pda_acc.key {
+            return Err(LotteryError::InvalidLotteryPda.into());
+        }
+
         msg!("PDA data is empty! Creating PDA account!");
-        let space: u64 = calculate_lottery_account_size(max_participants);
+        // lottery in Entries mode keeps only the header
+        let space: u64 = match storage_mode {
+            StorageMode::Slots => calculate_lottery_account_size(max_participants),
+            StorageMode::Entries => calculate_lottery_account_size(0),
+        };
         let rent = &Rent::from_account_info(rent_sysvar)?;
-        let lamports = rent.minimum_balance(space as usize);
-        let signer_seeds: &[&[_]] = &[LOTTERY_SEED.as_bytes(), &[lottery_bump]];
+        let lottery_id_bytes = lottery_id.to_le_bytes();
+        let signer_seeds: &[&[_]] = &[LOTTERY_SEED.as_bytes(), main_acc.key.as_ref(), &lottery_id_bytes, &[lottery_bump]];
 
         msg!("Required space for account is {}", space);
 
-        invoke_signed(
-            &system_instruction::create_account(
-                main_acc.key,
-                &lottery_pubkey,
-                lamports,
-                space,
-                program_id
-            ), 
-            &[main_acc.clone(), pda_acc.clone(), system_program.clone()], 
-            &[&signer_seeds]
-        )?;
+        create_pda_account(main_acc, pda_acc, system_program, rent, space as usize, program_id, signer_seeds)?;
+
+        init_lottery_account(&mut pda_acc.try_borrow_mut_data()?)?;
+    } else if pda_acc.owner != program_id {
+        return Err(LotteryError::InvalidLotteryAccount.into());
+    } else if storage_mode == StorageMode::Slots {
+        // account of restarted lottery must fit a slot per participant
+        // it's grown before the authority is checked, the whole transaction fails for anyone else
+        expand_lottery_account(main_acc, pda_acc, system_program, max_participants)?;
     }
 
-    msg!("Pda acc data after {:?}", &pda_acc.data.borrow());
+    let mut lottery_data = pda_acc.try_borrow_mut_data()?;
+    let (lottery_account, participants) = load_lottery_mut(&mut lottery_data)?;
 
-    let mut lottery_account = deserialize(&pda_acc.data.borrow()).unwrap();
+    // fresh account doesn't have authority yet, lottery started again must be restarted by its authority
+    let is_fresh = lottery_account.authority == Pubkey::default();
+    if is_fresh {
+        lottery_account.authority = *main_acc.key;
+        // rounds of a lottery re-created after CloseLottery don't repeat the rounds of the closed one
+        lottery_account.round = Clock::get()?.slot;
+    } else {
+        check_authority(lottery_account, main_acc)?;
 
-    let lifecycle_check = check_lottery_lifecycle(0, Option::Some(&lottery_account));
-    if lifecycle_check.is_err() {
-        // return lifecycle_check;
+        if lottery_account.lottery_id != lottery_id {
+            return Err(LotteryError::InvalidLotteryPda.into());
+        }
     }
 
-    msg!("Lottery account state before initializing: {:?}", lottery_account);
+    // participants of cancelled lottery have to get their bets back before it's started again
+    if lottery_account.lottery_state()? == LotteryState::CANCELLED && lottery_account.participants_count > 0 {
+        return Err(LotteryError::RefundsPending.into());
+    }
+
+    if !is_fresh {
+        check_lottery_lifecycle(0, Option::Some(lottery_account))?;
+    }
 
-    let new_participants_map: HashMap<Pubkey, u64> = HashMap::new();
-    lottery_account.winner = DEFAULT_WINNER_KEY;
-    lottery_account.participants = new_participants_map;
+    if lottery_account.storage_mode()? == StorageMode::Slots {
+        participants[..lottery_account.participants_count as usize].fill(ParticipantBet::default());
+    }
+    lottery_account.participants_count = 0;
+    lottery_account.winners = [DEFAULT_WINNER_KEY; MAX_WINNERS];
+    // entries of previous rounds don't count anymore
+    lottery_account.round = lottery_account.round.wrapping_add(1);
+    lottery_account.storage_mode = storage_mode as u8;
+    lottery_account.total_weight = 0;
+    lottery_account.total_lamports = 0;
+    lottery_account.draw_entropy = [0; 32];
 
     if lottery_account.max_participants != max_participants {
         lottery_account.max_participants = max_participants;
     }
 
-    lottery_account.lottery_state = LotteryState::IN_PROGRESS;
+    lottery_account.lottery_state = LotteryState::IN_PROGRESS as u8;
     lottery_account.lottery_start = unix_timestamp;
+    lottery_account.lottery_end = lottery_end;
+    lottery_account.lottery_id = lottery_id;
+    lottery_account.selection_mode = selection_mode as u8;
+    lottery_account.winners_amount = prize_tiers.len() as u8;
+    lottery_account.prize_tiers = [0; MAX_WINNERS];
+    lottery_account.prize_tiers[..prize_tiers.len()].copy_from_slice(&prize_tiers);
+    lottery_account.fee_bps = fee_bps;
+    lottery_account.treasury = treasury;
+    lottery_account.ticket_price = ticket_price;
+    lottery_account.min_bet = min_bet;
+    lottery_account.max_bet = max_bet;
+    lottery_account.total_tickets = 0;
+    lottery_account.mint = Pubkey::default();
+
+    if let Some(mint) = mint {
+        let vault_acc = next_account_info(accounts_iter)?;
+        let mint_acc = next_account_info(accounts_iter)?;
+        let token_program = next_account_info(accounts_iter)?;
+        if *mint_acc.key != mint {
+            return Err(LotteryError::InvalidMint.into());
+        }
+
+        open_token_vault(program_id, main_acc, pda_acc, vault_acc, mint_acc, token_program, system_program)?;
+        lottery_account.mint = mint;
+    }
+
+    lottery_account.commitment = [0; 32];
+    lottery_account.reveal_deadline = 0;
+    lottery_account.oracle = Pubkey::default();
+
+    match (commitment, oracle) {
+        (Some(commitment), _) => {
+            // seed is revealed at launch, which can't happen before the end of the lottery
+            if reveal_deadline <= lottery_end {
+                return Err(LotteryError::InvalidCommitment.into());
+            }
 
-    msg!("Lottery account state after initializing: {:?}", lottery_account);
+            lottery_account.randomness_mode = RandomnessMode::CommitReveal as u8;
+            lottery_account.commitment = commitment;
+            lottery_account.reveal_deadline = reveal_deadline;
+        }
+        (None, Some(oracle)) => {
+            lottery_account.randomness_mode = RandomnessMode::Oracle as u8;
+            lottery_account.oracle = oracle;
+        }
+        (None, None) => {
+            lottery_account.randomness_mode = RandomnessMode::SlotHashes as u8;
+        }
+    }
 
-    lottery_account.serialize(&mut &mut pda_acc.data.borrow_mut()[..])?;
+    msg!("Lottery {} is started, round {}, ends at {}", lottery_id, lottery_account.round, lottery_end);
 
     Ok(())
 }
 
 /// Accounts expected:
 /// 0. `[signer, writable]` Debit lamports from this account
-/// 1. `[writable]` Credit lamports to this account, must be PDA account
+/// 1. `[writable]` Credit lamports to this account, must be PDA account of the lottery
 /// 2. `[]` System program
+///
+/// Lotteries in SPL token expect instead of system program:
+/// 2. `[writable]` Token vault of the lottery, credit tokens here
+/// 3. `[]` SPL Token program
+/// 4. `[writable]` Participant's token account, debit tokens from it
+///
+/// Lotteries in Entries mode expect after them:
+/// 0. `[writable]` Participant's entry PDA, created on his first bet
+/// 1. `[]` System program
 pub fn handle_donate_instruction(program_id: &Pubkey, accounts: &[AccountInfo], lamports_amount: u64) -> ProgramResult {
     msg!("Executing donate instruction!");
 
@@ -191,22 +440,38 @@ pub fn handle_donate_instruction(program_id: &Pubkey, accounts: &[AccountInfo],
     let participant_acc = next_account_info(accounts_iter)?;
     // checking donater account
     if !participant_acc.is_writable && !participant_acc.is_signer {
-        return Err(ProgramError::InvalidAccountData);
+        return Err(LotteryError::InvalidParticipantAccount.into());
     }
 
     msg!("Participant pubkey: {:?}", participant_acc.key);
 
     let pda_acc = next_account_info(accounts_iter)?;
     // checking pda account
-    if !pda_acc.is_writable && pda_acc.owner != program_id {
-        return Err(ProgramError::IncorrectProgramId);
-    }
+    check_lottery_account(program_id, pda_acc)?;
+
+    // header is copied, so account data isn't borrowed during the transfer
+    let lottery_account = *load_lottery(&pda_acc.try_borrow_data()?)?.0;
+
+    if let Some(token_vault) = next_token_vault(program_id, &lottery_account, pda_acc.key, accounts_iter)? {
+        let source_acc = next_account_info(accounts_iter)?;
+
+        // token program checks that participant owns the source account and that mints match
+        invoke(
+            &spl_token::instruction::transfer(
+                token_vault.token_program.key,
+                source_acc.key,
+                token_vault.vault_acc.key,
+                participant_acc.key,
+                &[],
+                lamports_amount
+            )?,
+            &[source_acc.clone(), token_vault.vault_acc.clone(), participant_acc.clone(), token_vault.token_program.clone()],
+        )?;
 
-    // checking if it's still available to donate sol
-    // let lottery_availability = check_for_lottery_availability(pda_acc);
-    // if !lottery_availability.is_ok() {
-    //     return lottery_availability;
-    // }
+        msg!("transfer {} tokens from {:?} to vault {:?}: done", lamports_amount, source_acc.key, token_vault.vault_acc.key);
+
+        return Ok(());
+    }
 
     invoke(
         &transfer(participant_acc.key, pda_acc.key, lamports_amount),
@@ -218,185 +483,898 @@ pub fn handle_donate_instruction(program_id: &Pubkey, accounts: &[AccountInfo],
     Ok(())
 }
 
-/// Accounts expected:
-/// 0. `[signer, writable]` Debit lamports from this account
-/// 1. `[writable]` Credit lamports to this account, must be PDA account
-/// 2. `[]` System program
+/// Accounts expected are the same as in handle_donate_instruction
 pub fn update_main_acc_state(program_id: &Pubkey, accounts: &[AccountInfo], lamports_amount: u64) -> ProgramResult {
     let accounts_iter = &mut accounts.iter();
 
     let participant_acc = next_account_info(accounts_iter)?;
     // checking donater account
     if !participant_acc.is_writable && !participant_acc.is_signer {
-        return Err(ProgramError::InvalidAccountData);
+        return Err(LotteryError::InvalidParticipantAccount.into());
     }
 
     let pda_acc = next_account_info(accounts_iter)?;
     // checking pda account
-    if !pda_acc.is_writable && pda_acc.owner != program_id {
-        return Err(ProgramError::IncorrectProgramId);
-    }
+    check_lottery_account(program_id, pda_acc)?;
 
-    let mut lottery_account = deserialize(&pda_acc.data.borrow()).unwrap();
+    let mut lottery_data = pda_acc.try_borrow_mut_data()?;
+    let (lottery_account, participants) = load_lottery_mut(&mut lottery_data)?;
+
+    check_lottery_lifecycle(1, Option::Some(lottery_account))?;
+    check_lottery_not_ended(lottery_account, Clock::get()?.unix_timestamp)?;
+    check_bet_amount(lottery_account, lamports_amount)?;
+
+    // bet is checked to be a whole number of tickets
+    let tickets_amount = lamports_amount.checked_div(lottery_account.ticket_price).unwrap_or(0);
+
+    if lottery_account.storage_mode()? == StorageMode::Entries {
+        // accounts of the transfer go before the entry
+        let transfer_accounts_amount = if lottery_account.is_token_lottery() { 3 } else { 1 };
+        let accounts_iter = &mut accounts_iter.skip(transfer_accounts_amount);
+        let entry_acc = next_account_info(accounts_iter)?;
+        let system_program = next_account_info(accounts_iter)?;
+
+        if open_entry(program_id, participant_acc, pda_acc, entry_acc, system_program)? {
+            lottery_account.open_entries = lottery_account.open_entries.checked_add(1).ok_or(LotteryError::ArithmeticOverflow)?;
+        }
 
-    let lifecycle_check = check_lottery_lifecycle(1, Option::Some(&lottery_account));
-    if lifecycle_check.is_err() {
-        return lifecycle_check;
+        let mut entry_data = entry_acc.try_borrow_mut_data()?;
+        let entry = load_entry_mut(program_id, pda_acc.key, entry_acc, &mut entry_data)?;
+
+        // entry withdrawn from the end of the index has given its range back
+        if entry.round != lottery_account.round || (entry.lamports == 0 && entry.weight == 0) {
+            // first bet in this round, entry gets a range at the end of the cumulative index
+            msg!("This user hasn't donated yet, his bet is {:?} SOL", lamports_amount);
+            entry.round = lottery_account.round;
+            entry.lamports = lamports_amount;
+            entry.tickets = tickets_amount;
+            entry.start = lottery_account.total_weight;
+            entry.weight = lottery_account.entry_weight(lamports_amount, tickets_amount)?;
+
+            lottery_account.participants_count += 1;
+            if lottery_account.max_participants > 0 && lottery_account.participants_count >= lottery_account.max_participants {
+                lottery_account.lottery_state = LotteryState::BETS_CLOSED as u8;
+            }
+        } else {
+            // entry withdrawn from the middle keeps its range, so the draw can step over it
+            if entry.lamports == 0 {
+                return Err(LotteryError::EntryRangeClosed.into());
+            }
+
+            let amount_donated = entry.lamports.checked_add(lamports_amount).ok_or(LotteryError::ArithmeticOverflow)?;
+            let tickets_donated = entry.tickets.checked_add(tickets_amount).ok_or(LotteryError::ArithmeticOverflow)?;
+            let weight = lottery_account.entry_weight(amount_donated, tickets_donated)?;
+
+            // range can grow only while it's the last one, otherwise it would overlap with the next entries
+            if weight != entry.weight && entry.start + entry.weight != lottery_account.total_weight {
+                return Err(LotteryError::EntryRangeClosed.into());
+            }
+
+            msg!("This user has already donated {:?} amount of SOL, total sum for him is {:?}", entry.lamports, amount_donated);
+            entry.lamports = amount_donated;
+            entry.tickets = tickets_donated;
+            entry.weight = weight;
+        }
+
+        lottery_account.total_weight = lottery_account.total_weight
+            .max(entry.start.checked_add(entry.weight).ok_or(LotteryError::ArithmeticOverflow)?);
+        lottery_account.total_lamports = lottery_account.total_lamports.checked_add(lamports_amount).ok_or(LotteryError::ArithmeticOverflow)?;
+        lottery_account.total_tickets = lottery_account.total_tickets.checked_add(tickets_amount).ok_or(LotteryError::ArithmeticOverflow)?;
+
+        msg!("Entry after update: {:?}", entry);
+
+        return Ok(());
     }
 
     // checking whether this user has already donated 
-    if lottery_account.participants.contains_key(participant_acc.key) {
-        let amount_donated = lottery_account.participants[participant_acc.key] + lamports_amount;
-        msg!("This user has already donated {:?} amount of SOL, total sum for him is {:?}", lottery_account.participants[participant_acc.key], amount_donated);
-        lottery_account.participants.insert(participant_acc.key.clone(), amount_donated);
+    let index = match find_participant(lottery_account, participants, participant_acc.key) {
+        Ok(index) => {
+            let bet = &mut participants[index];
+            let amount_donated = bet.lamports.checked_add(lamports_amount).ok_or(LotteryError::ArithmeticOverflow)?;
+            msg!("This user has already donated {:?} amount of SOL, total sum for him is {:?}", bet.lamports, amount_donated);
+            bet.lamports = amount_donated;
+            bet.tickets = bet.tickets.checked_add(tickets_amount).ok_or(LotteryError::ArithmeticOverflow)?;
+
+            index
+        }
+        Err(index) => {
+            msg!("This user hasn't donated yet, his bet is {:?} SOL", lamports_amount);
+            insert_participant(lottery_account, participants, index, ParticipantBet {
+                pubkey: *participant_acc.key,
+                lamports: lamports_amount,
+                tickets: tickets_amount,
+                first_ticket: 0,
+            })?;
+
+            // if overall amount of users >= max_participants, change lottery state
+            if lottery_account.participants_count >= lottery_account.max_participants {
+                lottery_account.lottery_state = LotteryState::BETS_CLOSED as u8;
+            }
+
+            index
+        }
+    };
+
+    update_ticket_ranges(lottery_account, participants, index)?;
+    lottery_account.total_lamports = lottery_account.total_lamports.checked_add(lamports_amount).ok_or(LotteryError::ArithmeticOverflow)?;
+
+    msg!("Participant after update: {:?}", participants[index]);
+
+    Ok(())
+}
+
+/// Accounts expected:
+/// 0. `[signer]` Main account, authority of the lottery
+/// 1. `[writable]` PDA account of the lottery to transfer lamports from
+/// 2. `[writable]` Treasury account, credit fee here
+/// 3. `[writable]` Winner accounts, one per prize tier in tier order, credit lamports here
+///
+/// Lotteries in SPL token expect treasury's and winners' token accounts and between them:
+/// 3. `[writable]` Token vault of the lottery, debit tokens from it
+/// 4. `[]` SPL Token program
+pub fn complete_lottery(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Executing complete lottery instruction!");
+
+    let accounts_iter = &mut accounts.iter();
+
+    let main_acc = next_account_info(accounts_iter)?;
+
+    let pda_acc = next_account_info(accounts_iter)?;
+    // checking pda account
+    check_lottery_account(program_id, pda_acc)?;
+
+    let mut lottery_data = pda_acc.try_borrow_mut_data()?;
+    let (lottery_account, participants) = load_lottery_mut(&mut lottery_data)?;
+
+    // checking main account
+    check_authority(lottery_account, main_acc)?;
+
+    check_lottery_lifecycle(3, Option::Some(lottery_account))?;
+
+    // if complete lottery instruction was called before launch lottery or winners weren't chosen
+    if lottery_account.winners[0] == DEFAULT_WINNER_KEY {
+        return Err(LotteryError::WinnerNotChosen.into());
+    }
+
+    let treasury_acc = next_account_info(accounts_iter)?;
+    // checking treasury account, token account of the treasury for lotteries in SPL token
+    let treasury_key = if lottery_account.is_token_lottery() {
+        get_token_account_owner(treasury_acc, &lottery_account.mint)?
     } else {
-        msg!("This user hasn't donated yet, his bet is {:?} SOL", lamports_amount);
-        lottery_account.participants.insert(participant_acc.key.clone(), lamports_amount);
+        *treasury_acc.key
+    };
+    if !treasury_acc.is_writable || treasury_key != lottery_account.treasury {
+        return Err(LotteryError::InvalidTreasury.into());
+    }
+
+    let token_vault = next_token_vault(program_id, lottery_account, pda_acc.key, accounts_iter)?;
 
-        // if overall amount of users >= max_participants, change lottery state
-        if lottery_account.participants.len() as u32 >= lottery_account.max_participants {
-            lottery_account.lottery_state = LotteryState::BETS_CLOSED;
+    let winners_amount = lottery_account.winners_amount as usize;
+    let winner_accs: Vec<&AccountInfo> = accounts_iter.collect();
+    // checking winner accounts, they must go in the same order as tiers
+    if winner_accs.len() != winners_amount {
+        return Err(LotteryError::WinnerMismatch.into());
+    }
+    for (winner_acc, winner) in winner_accs.iter().zip(lottery_account.winners.iter()) {
+        let winner_key = if lottery_account.is_token_lottery() {
+            get_token_account_owner(winner_acc, &lottery_account.mint)?
+        } else {
+            *winner_acc.key
+        };
+        if !winner_acc.is_writable || winner_key != *winner {
+            return Err(LotteryError::WinnerMismatch.into());
         }
     }
 
-    msg!("Participants after update: {:?}", lottery_account.participants);
+    lottery_account.lottery_state = LotteryState::COMPLETED as u8;
+
+    let overall_donations = match lottery_account.storage_mode()? {
+        StorageMode::Slots => calculate_overall_donations(&participants[..lottery_account.participants_count as usize]),
+        StorageMode::Entries => Some(lottery_account.total_lamports).filter(|total| *total > 0),
+    };
+    let overall_donations = match overall_donations {
+        Some(overall_donations) => overall_donations,
+        None => return Err(LotteryError::NoDonations.into()),
+    };
+    let fee: u64 = calculate_fee(overall_donations, lottery_account.fee_bps)?;
+    msg!("Overall lottery donations are {}", overall_donations);
+
+    msg!("Calculated fee is {}", fee);
+    let prize = overall_donations.checked_sub(fee).ok_or(LotteryError::ArithmeticOverflow)?;
+
+    // every tier gets its percent of the prize rounded down, dust after rounding goes to the first tier
+    let tier_prizes: Vec<u64> = lottery_account.prize_tiers[..winners_amount].iter()
+        .map(|percent| ((prize as u128) * (*percent as u128) / 100) as u64)
+        .collect();
+    let dust = prize.checked_sub(tier_prizes.iter().sum::<u64>()).ok_or(LotteryError::ArithmeticOverflow)?;
+
+    // transfer lamports or tokens to winner accounts
+    for (tier, (winner_acc, tier_prize)) in winner_accs.iter().zip(tier_prizes.iter()).enumerate() {
+        let winner_lamports = if tier == 0 { tier_prize + dust } else { *tier_prize };
+
+        transfer_from_lottery(pda_acc, token_vault.as_ref(), winner_acc, winner_lamports)?;
+
+        msg!("Transfered tier {} payment of {} from: {:?} to: {:?}", tier, winner_lamports, pda_acc.key, winner_acc.key);
+    }
 
-    lottery_account.serialize(&mut &mut pda_acc.data.borrow_mut()[..])?;
+    // transfer fees to treasury account
+    transfer_from_lottery(pda_acc, token_vault.as_ref(), treasury_acc, fee)?;
+    // the whole pot is paid out
+    lottery_account.total_lamports = 0;
+
+    msg!("Transfered fee payment from: {:?} to: {:?}", pda_acc.key, treasury_acc.key);
+    msg!("Lottery state is changed to {:?}", lottery_account.lottery_state()?);
 
     Ok(())
 }
 
 /// Accounts expected:
-/// 0. `[signer, writable]` Main account, owner of program
-/// 1. `[writable]` PDA account to transfer lamports from
-/// 2. `[writable]` Winner account, credit lamports here
-/// 3. `[]` System program
-pub fn complete_lottery(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
-    msg!("Executing complete lottery instruction!");
+/// 0. `[signer]` Main account, authority of the lottery
+/// 1. `[writable]` PDA account of the lottery
+/// 2. `[]` SlotHashes sysvar, Instructions sysvar for lotteries in Oracle mode
+///
+/// In Oracle mode the previous instruction must be ed25519 verification of oracle's signature
+pub fn launch_lottery(program_id: &Pubkey, accounts: &[AccountInfo], reveal: Option<[u8; 32]>) -> ProgramResult {
+    msg!("Executing launch lottery instruction!");
 
     let accounts_iter = &mut accounts.iter();
 
     let main_acc = next_account_info(accounts_iter)?;
+
+    let pda_acc = next_account_info(accounts_iter)?;
+    // checking pda account
+    check_lottery_account(program_id, pda_acc)?;
+
+    let mut lottery_data = pda_acc.try_borrow_mut_data()?;
+    let (lottery_account, participants) = load_lottery_mut(&mut lottery_data)?;
+
     // checking main account
-    if !main_acc.is_signer {
-        return Err(ProgramError::InvalidAccountData);
+    check_authority(lottery_account, main_acc)?;
+
+    check_lottery_lifecycle(2, Option::Some(lottery_account))?;
+
+    let clock = Clock::get()?;
+    if clock.unix_timestamp < lottery_account.lottery_end as i64 {
+        return Err(LotteryError::LotteryNotEnded.into());
+    }
+
+    let sysvar_acc = next_account_info(accounts_iter)?;
+
+    if lottery_account.participants_count == 0 {
+        return Err(LotteryError::NoDonations.into());
+    }
+
+    // winner is derived from on-chain data or verified oracle output only, so the caller can't affect it
+    let entropy = match lottery_account.randomness_mode()? {
+        RandomnessMode::SlotHashes => get_slot_hashes_entropy(sysvar_acc)?,
+        RandomnessMode::CommitReveal => {
+            let reveal = reveal.ok_or(LotteryError::InvalidReveal)?;
+            check_reveal(lottery_account, &reveal, clock.unix_timestamp)?;
+
+            // operator's seed was fixed before any bet, slot hashes are unknown until launch
+            let slot_hashes_entropy = get_slot_hashes_entropy(sysvar_acc)?;
+            hashv(&[slot_hashes_entropy.as_ref(), &reveal])
+        }
+        RandomnessMode::Oracle => get_oracle_entropy(sysvar_acc, lottery_account, pda_acc.key, clock.slot)?,
+    };
+    lottery_account.lottery_state = LotteryState::LAUCNHED as u8;
+    // entropy is kept in every mode, so the draw can be checked off-chain against the stored participants
+    lottery_account.draw_entropy = entropy.to_bytes();
+
+    if lottery_account.storage_mode()? == StorageMode::Entries {
+        if (lottery_account.participants_count as usize) < lottery_account.winners_amount as usize {
+            return Err(LotteryError::NotEnoughParticipants.into());
+        }
+
+        // winner entries can't be passed before the entropy is known, they are resolved with ResolveWinners
+        msg!("Draw entropy is fixed, winners are resolved from entries");
+    } else {
+        let winners = select_winners(lottery_account, &participants[..lottery_account.participants_count as usize], &entropy, pda_acc.key)?;
+        lottery_account.winners[..winners.len()].copy_from_slice(&winners);
+        msg!("Winner pubkeys are {:?}", winners);
     }
 
+    msg!("Lottery state is changed to {:?}", lottery_account.lottery_state()?);
+
+    Ok(())
+}
+
+/// Accounts expected:
+/// 0. `[signer]` Main account, authority of the lottery
+/// 1. `[writable]` PDA account of the lottery
+pub fn set_treasury(program_id: &Pubkey, accounts: &[AccountInfo], treasury: Pubkey) -> ProgramResult {
+    msg!("Executing set treasury instruction!");
+
+    let accounts_iter = &mut accounts.iter();
+
+    let main_acc = next_account_info(accounts_iter)?;
+
     let pda_acc = next_account_info(accounts_iter)?;
     // checking pda account
-    if !pda_acc.is_writable && pda_acc.owner != program_id {
-        return Err(ProgramError::IncorrectProgramId);
+    check_lottery_account(program_id, pda_acc)?;
+
+    let mut lottery_data = pda_acc.try_borrow_mut_data()?;
+    let (lottery_account, _) = load_lottery_mut(&mut lottery_data)?;
+
+    // checking main account
+    check_authority(lottery_account, main_acc)?;
+
+    if treasury == Pubkey::default() {
+        return Err(LotteryError::InvalidTreasury.into());
     }
 
-    let mut lottery_account = deserialize(&pda_acc.data.borrow()).unwrap();
+    msg!("Changing treasury from {:?} to {:?}", lottery_account.treasury, treasury);
+    lottery_account.treasury = treasury;
 
-    let lifecycle_check = check_lottery_lifecycle(3, Option::Some(&lottery_account));
-    if lifecycle_check.is_err() {
-        return lifecycle_check;
+    Ok(())
+}
+
+/// First step of authority transfer, new authority has to accept it
+///
+/// Accounts expected:
+/// 0. `[signer]` Main account, authority of the lottery
+/// 1. `[writable]` PDA account of the lottery
+pub fn propose_authority(program_id: &Pubkey, accounts: &[AccountInfo], new_authority: Pubkey) -> ProgramResult {
+    msg!("Executing propose authority instruction!");
+
+    let accounts_iter = &mut accounts.iter();
+
+    let main_acc = next_account_info(accounts_iter)?;
+
+    let pda_acc = next_account_info(accounts_iter)?;
+    // checking pda account
+    check_lottery_account(program_id, pda_acc)?;
+
+    let mut lottery_data = pda_acc.try_borrow_mut_data()?;
+    let (lottery_account, _) = load_lottery_mut(&mut lottery_data)?;
+
+    // checking main account
+    check_authority(lottery_account, main_acc)?;
+
+    if new_authority == Pubkey::default() {
+        return Err(LotteryError::InvalidPendingAuthority.into());
+    }
+
+    msg!("Proposing authority {:?} instead of {:?}", new_authority, lottery_account.authority);
+    lottery_account.pending_authority = new_authority;
+
+    Ok(())
+}
+
+/// Second step of authority transfer
+///
+/// Accounts expected:
+/// 0. `[signer]` Proposed authority
+/// 1. `[writable]` PDA account of the lottery
+pub fn accept_authority(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Executing accept authority instruction!");
+
+    let accounts_iter = &mut accounts.iter();
+
+    let new_authority_acc = next_account_info(accounts_iter)?;
+    if !new_authority_acc.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
     }
 
-    // if complete lottery instruction was called before launch lottery or winner wasn't chosen
-    if lottery_account.winner == DEFAULT_WINNER_KEY {
-        return Err(ProgramError::InvalidAccountData);
+    let pda_acc = next_account_info(accounts_iter)?;
+    // checking pda account
+    check_lottery_account(program_id, pda_acc)?;
+
+    let mut lottery_data = pda_acc.try_borrow_mut_data()?;
+    let (lottery_account, _) = load_lottery_mut(&mut lottery_data)?;
+
+    if lottery_account.pending_authority == Pubkey::default() ||
+        lottery_account.pending_authority != *new_authority_acc.key {
+        return Err(LotteryError::InvalidPendingAuthority.into());
     }
 
-    let winner_acc = next_account_info(accounts_iter)?;
-    // checking winner account
-    if !winner_acc.is_writable || (winner_acc.key.clone() != lottery_account.winner) {
-        return Err(ProgramError::InvalidAccountData);
+    msg!("Authority is changed from {:?} to {:?}", lottery_account.authority, new_authority_acc.key);
+    lottery_account.authority = *new_authority_acc.key;
+    lottery_account.pending_authority = Pubkey::default();
+
+    Ok(())
+}
+
+/// Accounts expected:
+/// 0. `[signer]` Main account, authority of the lottery
+/// 1. `[writable]` PDA account of the lottery
+pub fn cancel_authority_proposal(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Executing cancel authority proposal instruction!");
+
+    let accounts_iter = &mut accounts.iter();
+
+    let main_acc = next_account_info(accounts_iter)?;
+
+    let pda_acc = next_account_info(accounts_iter)?;
+    // checking pda account
+    check_lottery_account(program_id, pda_acc)?;
+
+    let mut lottery_data = pda_acc.try_borrow_mut_data()?;
+    let (lottery_account, _) = load_lottery_mut(&mut lottery_data)?;
+
+    // checking main account
+    check_authority(lottery_account, main_acc)?;
+
+    if lottery_account.pending_authority == Pubkey::default() {
+        return Err(LotteryError::InvalidPendingAuthority.into());
     }
 
-    lottery_account.lottery_state = LotteryState::COMPLETED;
+    msg!("Cancelling proposal of authority {:?}", lottery_account.pending_authority);
+    lottery_account.pending_authority = Pubkey::default();
+
+    Ok(())
+}
+
+/// Cancelling the lottery before launch, after that participants can be refunded with RefundParticipants
+/// If operator of commit-reveal lottery missed reveal deadline, anyone can cancel it
+///
+/// Accounts expected:
+/// 0. `[signer]` Main account, authority of the lottery
+/// 1. `[writable]` PDA account of the lottery
+pub fn cancel_lottery(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Executing cancel lottery instruction!");
 
-    if calculate_overall_donations(&lottery_account).is_none() {
-        return Err(ProgramError::Custom(200));
+    let accounts_iter = &mut accounts.iter();
+
+    let main_acc = next_account_info(accounts_iter)?;
+
+    let pda_acc = next_account_info(accounts_iter)?;
+    // checking pda account
+    check_lottery_account(program_id, pda_acc)?;
+
+    let mut lottery_data = pda_acc.try_borrow_mut_data()?;
+    let (lottery_account, _) = load_lottery_mut(&mut lottery_data)?;
+
+    check_lottery_lifecycle(8, Option::Some(lottery_account))?;
+
+    let reveal_missed = lottery_account.randomness_mode()? == RandomnessMode::CommitReveal &&
+        Clock::get()?.unix_timestamp > lottery_account.reveal_deadline as i64;
+
+    // checking main account
+    if reveal_missed {
+        msg!("Reveal deadline was missed, lottery can be cancelled by anyone");
+    } else {
+        check_authority(lottery_account, main_acc)?;
     }
 
-    let overall_donations = calculate_overall_donations(&lottery_account).unwrap();
-    let fee: u64 = ((overall_donations as f64) * 0.01) as u64;
-    msg!("Overall lottery donations are {}", overall_donations);
+    lottery_account.winners = [DEFAULT_WINNER_KEY; MAX_WINNERS];
+    lottery_account.lottery_state = LotteryState::CANCELLED as u8;
 
-    msg!("Calculated fee is {}", fee);
-    let winner_lamports = overall_donations - fee;
+    msg!("Lottery state is changed to {:?}", lottery_account.lottery_state()?);
+
+    Ok(())
+}
+
+/// Returning recorded bets of cancelled lottery, can be called by anyone in batches
+///
+/// Accounts expected:
+/// 0. `[writable]` PDA account of the lottery to transfer lamports from
+/// 1. `[writable]` Participant accounts to refund, credit lamports here
+///
+/// Lotteries in SPL token expect participants' token accounts and before them:
+/// 1. `[writable]` Token vault of the lottery, debit tokens from it
+/// 2. `[]` SPL Token program
+///
+/// Lotteries in Entries mode expect every participant's account to be preceded by his `[writable]` entry PDA
+pub fn refund_participants(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Executing refund participants instruction!");
+
+    let accounts_iter = &mut accounts.iter();
+
+    let pda_acc = next_account_info(accounts_iter)?;
+    // checking pda account
+    check_lottery_account(program_id, pda_acc)?;
+
+    let mut lottery_data = pda_acc.try_borrow_mut_data()?;
+    let (lottery_account, participants) = load_lottery_mut(&mut lottery_data)?;
+
+    check_lottery_lifecycle(9, Option::Some(lottery_account))?;
+
+    let token_vault = next_token_vault(program_id, lottery_account, pda_acc.key, accounts_iter)?;
+
+    let storage_mode = lottery_account.storage_mode()?;
+    let refund_accs: Vec<&AccountInfo> = accounts_iter.collect();
+    let chunk_size = match storage_mode {
+        StorageMode::Slots => 1,
+        StorageMode::Entries => 2,
+    };
+
+    for refund_chunk in refund_accs.chunks(chunk_size) {
+        if refund_chunk.len() != chunk_size {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
 
-    if !LotteryAccount::check_pubkey(program_id, pda_acc.key) {
-        return Err(ProgramError::InvalidArgument);
+        let participant_acc = refund_chunk[chunk_size - 1];
+        if !participant_acc.is_writable {
+            return Err(LotteryError::InvalidParticipantAccount.into());
+        }
+
+        let participant_key = if lottery_account.is_token_lottery() {
+            get_token_account_owner(participant_acc, &lottery_account.mint)?
+        } else {
+            *participant_acc.key
+        };
+
+        // participant's bet is taken out, so he can't be refunded twice
+        let bet = match storage_mode {
+            StorageMode::Slots => {
+                let index = find_participant(lottery_account, participants, &participant_key)
+                    .map_err(|_| LotteryError::InvalidParticipantAccount)?;
+                let bet = remove_participant(lottery_account, participants, index).lamports;
+                update_ticket_ranges(lottery_account, participants, index)?;
+                lottery_account.total_lamports = lottery_account.total_lamports.checked_sub(bet).ok_or(LotteryError::ArithmeticOverflow)?;
+
+                bet
+            }
+            StorageMode::Entries => take_entry_bet(program_id, lottery_account, pda_acc.key, refund_chunk[0], &participant_key)?,
+        };
+
+        transfer_from_lottery(pda_acc, token_vault.as_ref(), participant_acc, bet)?;
+
+        msg!("Refunded {} lamports from: {:?} to: {:?}", bet, pda_acc.key, participant_acc.key);
     }
 
-    // transfer lamports to winner account
-    if **pda_acc.try_borrow_lamports()? < winner_lamports {
-        return Err(ProgramError::InsufficientFunds);
+    msg!("Participants left to refund: {}", lottery_account.participants_count);
+
+    Ok(())
+}
+
+/// Returning participant's bet while the draw hasn't happened yet
+///
+/// Accounts expected:
+/// 0. `[signer, writable]` Participant account, credit lamports here
+/// 1. `[writable]` PDA account of the lottery to transfer lamports from
+///
+/// Additional accounts for lotteries in SPL token:
+/// 2. `[writable]` Token vault of the lottery, debit tokens from it
+/// 3. `[]` SPL Token program
+/// 4. `[writable]` Participant's token account, credit tokens here
+///
+/// Lotteries in Entries mode expect participant's `[writable]` entry PDA after them
+pub fn withdraw_bet(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Executing withdraw bet instruction!");
+
+    let accounts_iter = &mut accounts.iter();
+
+    let participant_acc = next_account_info(accounts_iter)?;
+    // checking participant account
+    if !participant_acc.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if !participant_acc.is_writable {
+        return Err(LotteryError::InvalidParticipantAccount.into());
     }
 
-    **pda_acc.try_borrow_mut_lamports()? -= winner_lamports;
-    **winner_acc.try_borrow_mut_lamports()? += winner_lamports;
+    let pda_acc = next_account_info(accounts_iter)?;
+    // checking pda account
+    check_lottery_account(program_id, pda_acc)?;
+
+    let mut lottery_data = pda_acc.try_borrow_mut_data()?;
+    let (lottery_account, participants) = load_lottery_mut(&mut lottery_data)?;
+
+    check_lottery_lifecycle(10, Option::Some(lottery_account))?;
+    // bets are fixed after the end, so nobody can leave right before the draw
+    check_lottery_not_ended(lottery_account, Clock::get()?.unix_timestamp)?;
+
+    let token_vault = next_token_vault(program_id, lottery_account, pda_acc.key, accounts_iter)?;
+    // bet is returned to participant's own token account in SPL token lotteries
+    let destination_acc = if token_vault.is_some() {
+        let token_acc = next_account_info(accounts_iter)?;
+        if !token_acc.is_writable || get_token_account_owner(token_acc, &lottery_account.mint)? != *participant_acc.key {
+            return Err(LotteryError::InvalidTokenAccount.into());
+        }
+        token_acc
+    } else {
+        participant_acc
+    };
+
+    let bet = match lottery_account.storage_mode()? {
+        StorageMode::Slots => {
+            let index = find_participant(lottery_account, participants, participant_acc.key)
+                .map_err(|_| LotteryError::InvalidParticipantAccount)?;
+            let bet = remove_participant(lottery_account, participants, index).lamports;
+            update_ticket_ranges(lottery_account, participants, index)?;
+            lottery_account.total_lamports = lottery_account.total_lamports.checked_sub(bet).ok_or(LotteryError::ArithmeticOverflow)?;
+
+            bet
+        }
+        StorageMode::Entries => {
+            let entry_acc = next_account_info(accounts_iter)?;
+            take_entry_bet(program_id, lottery_account, pda_acc.key, entry_acc, participant_acc.key)?
+        }
+    };
+
+    transfer_from_lottery(pda_acc, token_vault.as_ref(), destination_acc, bet)?;
 
-    msg!("Transfered winner payment from: {:?} to: {:?}", pda_acc.key, winner_acc.key);
+    msg!("Returned bet of {} from: {:?} to: {:?}", bet, pda_acc.key, destination_acc.key);
 
-    // transfer fees to main account
-    if **pda_acc.try_borrow_lamports()? < fee {
-        return Err(ProgramError::InsufficientFunds);
+    // bets were closed because lottery was full, now there is a free slot
+    if lottery_account.lottery_state()? == LotteryState::BETS_CLOSED &&
+        lottery_account.participants_count < lottery_account.max_participants {
+        lottery_account.lottery_state = LotteryState::IN_PROGRESS as u8;
     }
 
-    **pda_acc.try_borrow_mut_lamports()? -= fee;
-    **main_acc.try_borrow_mut_lamports()? += fee;
+    msg!("Lottery state is {:?}, participants count is {}", lottery_account.lottery_state()?, lottery_account.participants_count);
+
+    Ok(())
+}
+
+/// Choosing winners of a lottery in Entries mode from the entropy fixed at launch, can be called by anyone
+///
+/// Accounts expected:
+/// 0. `[writable]` PDA account of the lottery
+/// 1. `[]` Entry PDAs, that cover the drawn numbers, in draw order
+pub fn resolve_winners(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Executing resolve winners instruction!");
+
+    let accounts_iter = &mut accounts.iter();
+
+    let pda_acc = next_account_info(accounts_iter)?;
+    // checking pda account
+    check_lottery_account(program_id, pda_acc)?;
+
+    let mut lottery_data = pda_acc.try_borrow_mut_data()?;
+    let (lottery_account, _) = load_lottery_mut(&mut lottery_data)?;
+
+    check_lottery_lifecycle(11, Option::Some(lottery_account))?;
 
-    msg!("Transfered fee payment from: {:?} to: {:?}", pda_acc.key, main_acc.key);
-    msg!("Lottery account after changing state {:?}", lottery_account);
+    if lottery_account.storage_mode()? != StorageMode::Entries {
+        return Err(LotteryError::WrongLifecycleState.into());
+    }
 
-    lottery_account.serialize(&mut &mut pda_acc.data.borrow_mut()[..])?;
+    let winners = resolve_winner_entries(program_id, lottery_account, pda_acc.key, accounts_iter)?;
+    lottery_account.winners[..winners.len()].copy_from_slice(&winners);
+    msg!("Winner pubkeys are {:?}", winners);
 
     Ok(())
 }
 
+/// Growing lottery account in place to fit more participants, bets placed so far are kept
+/// Runtime lets an account grow by MAX_PERMITTED_DATA_INCREASE bytes per instruction,
+/// so one call adds up to about 182 slots, bigger lotteries are expanded in several calls
+///
 /// Accounts expected:
-/// 0. `[signer]` Main account, owner of program
-/// 1. `[writable]` PDA account to transfer lamports from
+/// 0. `[signer, writable]` Main account, authority of the lottery, pays rent of the added slots
+/// 1. `[writable]` PDA account of the lottery
 /// 2. `[]` System program
-pub fn launch_lottery(program_id: &Pubkey, accounts: &[AccountInfo], hashes: Vec<u8>) -> ProgramResult {
-    msg!("Executing launch lottery instruction!");
+pub fn expand_lottery(program_id: &Pubkey, accounts: &[AccountInfo], max_participants: u32) -> ProgramResult {
+    msg!("Executing expand lottery instruction!");
 
     let accounts_iter = &mut accounts.iter();
 
     let main_acc = next_account_info(accounts_iter)?;
-    // checking main account
-    if !main_acc.is_signer {
-        return Err(ProgramError::InvalidAccountData);
+
+    let pda_acc = next_account_info(accounts_iter)?;
+    // checking pda account
+    check_lottery_account(program_id, pda_acc)?;
+
+    let system_program = next_account_info(accounts_iter)?;
+
+    {
+        let lottery_data = pda_acc.try_borrow_data()?;
+        let (lottery_account, _) = load_lottery(&lottery_data)?;
+
+        // checking main account
+        check_authority(lottery_account, main_acc)?;
+
+        // lottery in Entries mode has no slots to grow
+        if lottery_account.storage_mode()? != StorageMode::Slots {
+            return Err(LotteryError::NoParticipantSlots.into());
+        }
+
+        // used slots can't be cut off
+        if max_participants < lottery_account.max_participants {
+            return Err(LotteryError::MaxParticipantsDecreased.into());
+        }
+    }
+
+    expand_lottery_account(main_acc, pda_acc, system_program, max_participants)?;
+
+    let mut lottery_data = pda_acc.try_borrow_mut_data()?;
+    let (lottery_account, _) = load_lottery_mut(&mut lottery_data)?;
+
+    msg!("Changing max participants from {} to {}", lottery_account.max_participants, max_participants);
+    lottery_account.max_participants = max_participants;
+
+    // bets were closed because lottery was full, now there are free slots
+    if lottery_account.lottery_state()? == LotteryState::BETS_CLOSED &&
+        lottery_account.participants_count < lottery_account.max_participants {
+        lottery_account.lottery_state = LotteryState::IN_PROGRESS as u8;
     }
 
+    Ok(())
+}
+
+/// Closing completed or cancelled lottery, its data is wiped and rent goes back
+///
+/// Accounts expected:
+/// 0. `[signer]` Main account, authority of the lottery
+/// 1. `[writable]` PDA account of the lottery to close
+/// 2. `[writable]` Authority or treasury account, credit all lamports of the lottery here
+///
+/// Additional accounts for lotteries in SPL token, the vault is closed as well:
+/// 3. `[writable]` Token vault of the lottery
+/// 4. `[]` SPL Token program
+/// 5. `[writable]` Token account of the authority or treasury, credit tokens left in the vault here
+pub fn close_lottery(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Executing close lottery instruction!");
+
+    let accounts_iter = &mut accounts.iter();
+
+    let main_acc = next_account_info(accounts_iter)?;
+
     let pda_acc = next_account_info(accounts_iter)?;
     // checking pda account
-    if !pda_acc.is_writable && pda_acc.owner != program_id {
-        return Err(ProgramError::IncorrectProgramId);
+    check_lottery_account(program_id, pda_acc)?;
+
+    let destination_acc = next_account_info(accounts_iter)?;
+
+    {
+        let lottery_data = pda_acc.try_borrow_data()?;
+        let (lottery_account, _) = load_lottery(&lottery_data)?;
+
+        // checking main account
+        check_authority(lottery_account, main_acc)?;
+
+        check_lottery_lifecycle(13, Option::Some(lottery_account))?;
+
+        // participants of cancelled lottery have to be refunded first
+        if lottery_account.lottery_state()? == LotteryState::CANCELLED && lottery_account.participants_count > 0 {
+            return Err(LotteryError::RefundsPending.into());
+        }
+
+        // pot of completed lottery is paid out and refunds are done, so nothing is owed to anybody
+        if lottery_account.total_lamports > 0 {
+            return Err(LotteryError::FundsPending.into());
+        }
+
+        // entries of a re-created lottery would have the same addresses, so they must be gone first
+        if lottery_account.open_entries > 0 {
+            return Err(LotteryError::EntriesOpen.into());
+        }
+
+        if !destination_acc.is_writable ||
+            (*destination_acc.key != lottery_account.authority && *destination_acc.key != lottery_account.treasury) {
+            return Err(LotteryError::InvalidDestination.into());
+        }
+
+        if let Some(token_vault) = next_token_vault(program_id, lottery_account, pda_acc.key, accounts_iter)? {
+            let destination_token_acc = next_account_info(accounts_iter)?;
+            close_token_vault(pda_acc, &token_vault, destination_acc, destination_token_acc)?;
+        }
     }
 
-    msg!("Recieved hash string {:?}", hashes);
+    let lamports = close_program_account(pda_acc, destination_acc)?;
+
+    msg!("Closed lottery {:?}, returned {} lamports to {:?}", pda_acc.key, lamports, destination_acc.key);
+
+    Ok(())
+}
+
+/// Upgrading lottery account of v1 layout in place, v1 header is rewritten field by field into the current one
+/// and participant slots are moved behind it
+///
+/// Accounts expected:
+/// 0. `[signer, writable]` Main account, authority of the lottery, pays rent of the added bytes
+/// 1. `[writable]` PDA account of the lottery
+/// 2. `[]` System program
+/// 3. `[]` Every entry PDA of a lottery in Entries mode, that hasn't been closed yet
+///
+/// v1 didn't count entry PDAs, so the count is taken from the passed entries,
+/// an entry left out would let CloseLottery through before it's closed
+pub fn migrate_lottery(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Executing migrate lottery instruction!");
+
+    let accounts_iter = &mut accounts.iter();
 
-    let mut lottery_account = deserialize(&pda_acc.data.borrow()).unwrap();
+    let main_acc = next_account_info(accounts_iter)?;
 
-    let lifecycle_check = check_lottery_lifecycle(2, Option::Some(&lottery_account));
-    if lifecycle_check.is_err() {
-        return lifecycle_check;
+    let pda_acc = next_account_info(accounts_iter)?;
+    // checking pda account
+    check_lottery_account(program_id, pda_acc)?;
+
+    let system_program = next_account_info(accounts_iter)?;
+
+    let version = get_lottery_version(&pda_acc.try_borrow_data()?)?;
+    match version {
+        LOTTERY_VERSION => {
+            msg!("Lottery account is already of version {}", version);
+            return Ok(());
+        }
+        1 => {}
+        _ => return Err(LotteryError::UnsupportedVersion.into()),
     }
 
-    let winner_num: usize = calculate_random_number(&hashes, lottery_account.participants.len().try_into().unwrap());
+    let v1_len = pda_acc.data_len();
+    // v1 header is copied out before the account is moved
+    let v1_header: LotteryAccountV1 = *bytemuck::try_from_bytes(&pda_acc.try_borrow_data()?[..LOTTERY_V1_HEADER_SIZE])
+        .map_err(|_| LotteryError::InvalidLotteryAccount)?;
 
-    msg!("Winner index is {}", winner_num);
-    let participants_pubkeys: Vec<Pubkey> = lottery_account.participants
-                                    .clone()
-                                    .into_iter()
-                                    .map(|(pubkey, _)| pubkey)
-                                    .collect();
-    let winner_pubkey = participants_pubkeys.get(winner_num);
-    if winner_pubkey.is_some() {
-        lottery_account.winner = winner_pubkey.unwrap().clone();
-        lottery_account.lottery_state = LotteryState::LAUCNHED;
-        msg!("Winner pubkey is {:?}", lottery_account.winner);
-    };
+    // checking main account
+    let mut lottery_header = LotteryAccount::from_v1(&v1_header);
+    check_authority(&lottery_header, main_acc)?;
+
+    // participant slots keep their layout, they are moved behind the bigger header
+    let header_size = size_of::<LotteryAccount>();
+    grow_lottery_account(main_acc, pda_acc, system_program, v1_len - LOTTERY_V1_HEADER_SIZE + header_size)?;
+
+    let mut lottery_data = pda_acc.try_borrow_mut_data()?;
+    lottery_data.copy_within(LOTTERY_V1_HEADER_SIZE..v1_len, header_size);
+
+    // v1 kept the sum of bets for Entries mode only and didn't count entries
+    match lottery_header.storage_mode()? {
+        StorageMode::Slots => {
+            let slots: &[ParticipantBet] = bytemuck::try_cast_slice(&lottery_data[header_size..])
+                .map_err(|_| LotteryError::InvalidLotteryAccount)?;
+            let participants = slots.get(..lottery_header.participants_count as usize)
+                .ok_or(LotteryError::InvalidParticipantsCount)?;
+            lottery_header.total_lamports = participants.iter()
+                .try_fold(0u64, |sum, bet| sum.checked_add(bet.lamports))
+                .ok_or(LotteryError::ArithmeticOverflow)?;
+        }
+        StorageMode::Entries => {
+            lottery_header.open_entries = count_entries(program_id, pda_acc.key, accounts_iter)?;
+            msg!("Open entries: {}", lottery_header.open_entries);
+        }
+    }
+
+    lottery_data[..header_size].copy_from_slice(bytemuck::bytes_of(&lottery_header));
+    // header is checked the same way every handler reads it
+    load_lottery(&lottery_data)?;
+
+    msg!("Lottery account migrated from version {} to {}", version, LOTTERY_VERSION);
+
+    Ok(())
+}
+
+/// Closing participant's entry, which doesn't hold a bet of the current round anymore, rent goes back to participant
+/// Can be called by anyone, so entries don't keep a finished lottery from being closed
+///
+/// Accounts expected:
+/// 0. `[writable]` PDA account of the lottery
+/// 1. `[writable]` Participant's entry PDA
+/// 2. `[writable]` Participant account, credit rent of the entry here
+pub fn close_entry(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Executing close entry instruction!");
+
+    let accounts_iter = &mut accounts.iter();
+
+    let pda_acc = next_account_info(accounts_iter)?;
+    // checking pda account
+    check_lottery_account(program_id, pda_acc)?;
+
+    let entry_acc = next_account_info(accounts_iter)?;
+    let participant_acc = next_account_info(accounts_iter)?;
+
+    let mut lottery_data = pda_acc.try_borrow_mut_data()?;
+    let (lottery_account, _) = load_lottery_mut(&mut lottery_data)?;
+
+    {
+        let entry_data = entry_acc.try_borrow_data()?;
+        let entry = load_entry(program_id, pda_acc.key, entry_acc, &entry_data)?;
+
+        if entry.participant != *participant_acc.key || !participant_acc.is_writable {
+            return Err(LotteryError::InvalidParticipantAccount.into());
+        }
+
+        // bets of previous rounds are settled, bet of the current one only after it's paid out or refunded
+        let lottery_state = lottery_account.lottery_state()?;
+        let settled = entry.round != lottery_account.round ||
+            lottery_state == LotteryState::COMPLETED ||
+            (lottery_state == LotteryState::CANCELLED && entry.lamports == 0);
+        if !settled {
+            return Err(LotteryError::EntryInUse.into());
+        }
+    }
+
+    if !entry_acc.is_writable {
+        return Err(LotteryError::InvalidEntry.into());
+    }
 
-    msg!("Lottery account after changing state {:?}", lottery_account);
+    let lamports = close_program_account(entry_acc, participant_acc)?;
+    lottery_account.open_entries = lottery_account.open_entries.checked_sub(1).ok_or(LotteryError::ArithmeticOverflow)?;
 
-    lottery_account.serialize(&mut &mut pda_acc.data.borrow_mut()[..])?;
+    msg!("Closed entry {:?}, returned {} lamports to {:?}", entry_acc.key, lamports, participant_acc.key);
 
     Ok(())
 }
\ No newline at end of file
@@ -1,3 +1,4 @@
+#[cfg(not(feature = "no-entrypoint"))]
 use solana_program::entrypoint;
 
 pub mod instructions;
@@ -5,7 +6,12 @@ pub mod state;
 pub mod processor;
 pub mod error;
 pub mod utils;
+/// instruction builders and account decoding for off-chain Rust clients
+#[cfg(feature = "no-entrypoint")]
+pub mod client;
 
+#[cfg(not(feature = "no-entrypoint"))]
 use crate::processor::process_instruction;
 
+#[cfg(not(feature = "no-entrypoint"))]
 entrypoint!(process_instruction);
\ No newline at end of file
@@ -2,9 +2,12 @@ use solana_program::{
     account_info::{AccountInfo},
     entrypoint::ProgramResult,
     msg,
+    program_error::PrintProgramError,
     pubkey::Pubkey
 };
 
+use crate::error::LotteryError;
+
 
 use crate::instructions::LotteryInstructions;
 use crate::instructions::{
@@ -12,7 +15,19 @@ use crate::instructions::{
     handle_donate_instruction, 
     update_main_acc_state, 
     launch_lottery, 
-    complete_lottery
+    complete_lottery,
+    set_treasury,
+    propose_authority,
+    accept_authority,
+    cancel_authority_proposal,
+    cancel_lottery,
+    refund_participants,
+    withdraw_bet,
+    resolve_winners,
+    expand_lottery,
+    close_lottery,
+    migrate_lottery,
+    close_entry
 };
 
 
@@ -27,8 +42,8 @@ pub fn process_instruction(
 
     msg!("Recieved instruction: {:?}", instruction);
 
-    return match instruction {
-        LotteryInstructions::StartLottery(max_participants, unix_timestamp) => start_lottery(program_id, accounts, max_participants, unix_timestamp),
+    let result = match instruction {
+        LotteryInstructions::StartLottery(args) => start_lottery(program_id, accounts, args),
         LotteryInstructions::DonateInstruction(lamports_amount) => {
             // first we have to accept donation
             handle_donate_instruction(program_id, accounts, lamports_amount)?;
@@ -37,7 +52,26 @@ pub fn process_instruction(
 
             Ok(())
         }, 
-        LotteryInstructions::LaunchLottery(hashes) => launch_lottery(program_id, accounts, hashes),
+        LotteryInstructions::LaunchLottery(reveal) => launch_lottery(program_id, accounts, reveal),
         LotteryInstructions::CompleteLottery => complete_lottery(program_id, accounts),
+        LotteryInstructions::SetTreasury(treasury) => set_treasury(program_id, accounts, treasury),
+        LotteryInstructions::ProposeAuthority(new_authority) => propose_authority(program_id, accounts, new_authority),
+        LotteryInstructions::AcceptAuthority => accept_authority(program_id, accounts),
+        LotteryInstructions::CancelAuthorityProposal => cancel_authority_proposal(program_id, accounts),
+        LotteryInstructions::CancelLottery => cancel_lottery(program_id, accounts),
+        LotteryInstructions::RefundParticipants => refund_participants(program_id, accounts),
+        LotteryInstructions::WithdrawBet => withdraw_bet(program_id, accounts),
+        LotteryInstructions::ResolveWinners => resolve_winners(program_id, accounts),
+        LotteryInstructions::ExpandLottery(max_participants) => expand_lottery(program_id, accounts, max_participants),
+        LotteryInstructions::CloseLottery => close_lottery(program_id, accounts),
+        LotteryInstructions::MigrateLottery => migrate_lottery(program_id, accounts),
+        LotteryInstructions::CloseEntry => close_entry(program_id, accounts),
     };
+
+    if let Err(error) = &result {
+        // logging typed lottery error, so clients can tell failures apart
+        error.print::<LotteryError>();
+    }
+
+    result
 }
\ No newline at end of file
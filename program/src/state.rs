@@ -1,16 +1,67 @@
 use borsh::{BorshDeserialize, BorshSerialize};
+use bytemuck::{Pod, Zeroable};
+use num_derive::FromPrimitive;
 use solana_program::pubkey::Pubkey;
-use std::collections::HashMap;
+use std::mem::size_of;
 
 pub const LOTTERY_SEED: &str = "lottery";
+/// token vault of a lottery is a PDA, derived from this seed and lottery pubkey
+pub const VAULT_SEED: &str = "vault";
+/// participant's entry is a PDA, derived from this seed, lottery pubkey and participant pubkey
+pub const ENTRY_SEED: &str = "entry";
+/// lottery account starts with these bytes, accounts of v1 layout don't have them
+pub const LOTTERY_DISCRIMINATOR: [u8; 7] = *b"LOTTERY";
+/// current layout of lottery account, v1 layout is kept in LotteryAccountV1
+pub const LOTTERY_VERSION: u8 = 2;
+/// size of v1 header, it's followed by participant slots the same way
+pub const LOTTERY_V1_HEADER_SIZE: usize = size_of::<LotteryAccountV1>();
 pub const DEFAULT_WINNER_KEY: Pubkey = Pubkey::new_from_array([0; 32]);
+/// max amount of prize tiers, every tier has its own winner
+pub const MAX_WINNERS: usize = 10;
+/// house fee can't be greater than 10%
+pub const MAX_FEE_BPS: u16 = 1_000;
+/// 100% in basis points
+pub const BPS_DENOMINATOR: u64 = 10_000;
 
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
+#[allow(non_camel_case_types)]
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, FromPrimitive)]
 pub enum LotteryState {
     BETS_CLOSED,
     IN_PROGRESS,
     LAUCNHED, 
-    COMPLETED
+    COMPLETED,
+    CANCELLED
+}
+
+/// source of randomness for the winner draw
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq, FromPrimitive)]
+pub enum RandomnessMode {
+    /// winner is derived from SlotHashes sysvar only
+    SlotHashes,
+    /// operator commits to a secret seed at start and reveals it at launch, seed is mixed with SlotHashes
+    CommitReveal,
+    /// oracle signs lottery pubkey, round and draw slot with ed25519, winner is derived from the signature
+    Oracle,
+}
+
+/// how participant's bet affects chance to win
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq, FromPrimitive)]
+pub enum SelectionMode {
+    /// every participant has an equal chance
+    Uniform,
+    /// chance to win is proportional to the amount of lamports donated
+    Weighted,
+}
+
+/// where participants' bets are kept
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq, FromPrimitive)]
+pub enum StorageMode {
+    /// in slots right after lottery header, amount of participants is limited by account size
+    Slots,
+    /// in entry PDA per participant, lottery header keeps running totals only
+    /// in Weighted and ticket lotteries participant can add to his bet only while nobody has bet after him,
+    /// entry is closed with CloseEntry after the round to return its rent
+    Entries,
 }
 
 impl PartialEq for LotteryState {
@@ -18,13 +69,110 @@ impl PartialEq for LotteryState {
         core::mem::discriminant(self) == core::mem::discriminant(other)
     }
 }
-/// borsh deserialize struct from top to bottom
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
+/// fixed part of lottery account, read and written in place
+/// discriminator and version take 8 bytes in front, other fields go from the widest to the narrowest,
+/// so there is no padding and participants right after it are aligned
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
 pub struct LotteryAccount {
+    pub discriminator: [u8; 7], // 7 bytes, LOTTERY_DISCRIMINATOR
+    pub version: u8, // 1 byte, LOTTERY_VERSION
+    pub winners: [Pubkey; MAX_WINNERS], // 32 bytes * MAX_WINNERS, tier order, unused tiers are DEFAULT_WINNER_KEY
+    pub authority: Pubkey, // 32 bytes, creator of the lottery, the only one allowed to manage it
+    pub treasury: Pubkey, // 32 bytes, collected fees are sent here
+    pub pending_authority: Pubkey, // 32 bytes, proposed new authority, zeroed if there is no proposal
+    pub oracle: Pubkey, // 32 bytes, signer of draw messages, zeroed if lottery is not in Oracle mode
+    pub mint: Pubkey, // 32 bytes, SPL token of the pot, kept in the vault PDA, zeroed if lottery is in lamports
+    pub commitment: [u8; 32], // 32 bytes, hash of operator's secret seed, zeroed in SlotHashes mode
+    pub draw_entropy: [u8; 32], // 32 bytes, entropy fixed at launch, winners are drawn from it, in Entries mode by ResolveWinners
+    pub lottery_start: u64, // 8 bytes
+    pub lottery_end: u64, // 8 bytes, unix timestamp, bets are accepted and withdrawn before it, the draw happens after it
+    pub lottery_id: u64, // 8 bytes
+    pub reveal_deadline: u64, // 8 bytes, unix timestamp, seed can't be revealed after it
+    pub ticket_price: u64, // 8 bytes, every bet must be a whole number of tickets, 0 if lottery has no tickets
+    pub min_bet: u64, // 8 bytes, min lamports per bet, 0 if there is no limit
+    pub max_bet: u64, // 8 bytes, max lamports per bet, 0 if there is no limit
+    pub total_tickets: u64, // 8 bytes, amount of tickets bought by all participants, 0 if lottery has no tickets
+    pub round: u64, // 8 bytes, incremented on every start, entries of previous rounds are ignored
+    pub total_weight: u64, // 8 bytes, end of the cumulative index of entries in Entries mode
+    pub total_lamports: u64, // 8 bytes, sum of bets, which haven't been paid out or returned
     pub max_participants: u32, // 4 bytes
-    pub lottery_state: LotteryState, // 1 byte
-    pub winner: Pubkey, // 32 bytes
+    pub participants_count: u32, // 4 bytes, amount of used participant slots
+    pub fee_bps: u16, // 2 bytes, house fee in basis points of overall donations
+    pub lottery_state: u8, // 1 byte, LotteryState
+    pub randomness_mode: u8, // 1 byte, RandomnessMode
+    pub selection_mode: u8, // 1 byte, SelectionMode
+    pub winners_amount: u8, // 1 byte
+    pub prize_tiers: [u8; MAX_WINNERS], // MAX_WINNERS bytes, percent of the prize per tier, sum of used tiers is 100
+    pub storage_mode: u8, // 1 byte, StorageMode
+    pub reserved: [u8; 3], // 3 bytes, keeps open_entries aligned
+    pub open_entries: u32, // 4 bytes, amount of entry PDAs, which haven't been closed yet, kept across rounds, taken from reserved bytes
+}
+// total - 672 bytes
+
+/// header of v1 lottery account, frozen as it was deployed, only MigrateLottery reads it
+/// it has no discriminator and version, participant slots go right after it
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+pub struct LotteryAccountV1 {
+    pub winners: [Pubkey; MAX_WINNERS], // 32 bytes * MAX_WINNERS
+    pub authority: Pubkey, // 32 bytes
+    pub treasury: Pubkey, // 32 bytes
+    pub pending_authority: Pubkey, // 32 bytes
+    pub oracle: Pubkey, // 32 bytes
+    pub mint: Pubkey, // 32 bytes
+    pub commitment: [u8; 32], // 32 bytes
+    pub draw_entropy: [u8; 32], // 32 bytes
     pub lottery_start: u64, // 8 bytes
-    pub participants: HashMap<Pubkey, u64>, // 40 bytes per (key; value), 4 bytes for hashmap capacity
+    pub lottery_end: u64, // 8 bytes
+    pub lottery_id: u64, // 8 bytes
+    pub reveal_deadline: u64, // 8 bytes
+    pub ticket_price: u64, // 8 bytes
+    pub min_bet: u64, // 8 bytes
+    pub max_bet: u64, // 8 bytes
+    pub total_tickets: u64, // 8 bytes
+    pub round: u64, // 8 bytes
+    pub total_weight: u64, // 8 bytes
+    pub total_lamports: u64, // 8 bytes, sum of bets in Entries mode only
+    pub max_participants: u32, // 4 bytes
+    pub participants_count: u32, // 4 bytes
+    pub fee_bps: u16, // 2 bytes
+    pub lottery_state: u8, // 1 byte
+    pub randomness_mode: u8, // 1 byte
+    pub selection_mode: u8, // 1 byte
+    pub winners_amount: u8, // 1 byte
+    pub prize_tiers: [u8; MAX_WINNERS], // MAX_WINNERS bytes
+    pub storage_mode: u8, // 1 byte
+    pub reserved: [u8; 7], // 7 bytes
+}
+// total - 664 bytes
+
+/// participant slot, slots go right after lottery account header, max_participants of them
+/// used slots are kept sorted by pubkey, so winner index maps to the same participant on-chain and off-chain,
+/// ticket ranges go one after another in the same order
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Pod, Zeroable)]
+pub struct ParticipantBet {
+    pub pubkey: Pubkey, // 32 bytes
+    pub lamports: u64, // 8 bytes, overall amount donated by the participant
+    pub tickets: u64, // 8 bytes, amount of tickets bought, 0 if lottery has no tickets
+    pub first_ticket: u64, // 8 bytes, participant owns tickets [first_ticket, first_ticket + tickets)
+}
+// total - 56 bytes per slot
+
+/// participant's bet in Entries mode, the entry covers [start, start + weight) of the lottery cumulative index
+/// weight is amount of tickets, lamports or 1 depending on lottery's ticket price and selection mode
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Pod, Zeroable)]
+pub struct LotteryEntry {
+    pub lottery: Pubkey, // 32 bytes
+    pub participant: Pubkey, // 32 bytes
+    pub round: u64, // 8 bytes, round of the lottery the bet was placed in
+    pub lamports: u64, // 8 bytes, 0 after the bet is withdrawn or refunded, such entry can't win
+    pub tickets: u64, // 8 bytes, 0 if lottery has no tickets
+    pub start: u64, // 8 bytes
+    pub weight: u64, // 8 bytes
+    pub bump: u8, // 1 byte
+    pub reserved: [u8; 7], // 7 bytes
 }
-// total - 45 bytes for stationary data, 40 bytes * max_participants + 4 bytes for hash map size
\ No newline at end of file
+// total - 112 bytes
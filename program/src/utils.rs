@@ -1,98 +1,726 @@
 use std::{mem::size_of};
 
 use solana_program::{
-    account_info::AccountInfo,
-    entrypoint::ProgramResult,
+    account_info::{AccountInfo, next_account_info},
+    entrypoint::{ProgramResult, MAX_PERMITTED_DATA_INCREASE},
+    clock::Slot,
+    ed25519_program,
+    hash::{hashv, Hash},
     program_error::ProgramError,
     msg,
+    program::{invoke, invoke_signed},
+    program_pack::Pack,
     pubkey::Pubkey,
+    system_instruction,
+    system_program,
+    sysvar::{instructions, rent::Rent, slot_hashes, Sysvar},
 };
-use borsh::{BorshDeserialize};
+use num_traits::FromPrimitive;
 
-use crate::state::{LotteryState, LotteryAccount, LOTTERY_SEED};
+use crate::error::LotteryError;
+use crate::state::{LotteryState, LotteryAccount, LotteryAccountV1, LotteryEntry, ParticipantBet, RandomnessMode, SelectionMode, StorageMode, LOTTERY_SEED, VAULT_SEED, ENTRY_SEED, LOTTERY_DISCRIMINATOR, LOTTERY_VERSION, LOTTERY_V1_HEADER_SIZE, MAX_WINNERS, MAX_FEE_BPS, BPS_DENOMINATOR};
 
+/// amount of the most recent slot hashes, used for winner calculation
+const SLOT_HASHES_ENTRIES_NUM: usize = 5;
+/// u64 amount of entries in the beginning of SlotHashes sysvar
+const SLOT_HASHES_LEN_SIZE: usize = 8;
+/// u64 slot + 32 bytes hash
+const SLOT_HASHES_ENTRY_SIZE: usize = 40;
+/// oracle signature is accepted only for one of the last slots, so old signatures can't be replayed
+/// the authority can still pick any signature the oracle produced for the lottery round within this window
+const MAX_DRAW_SLOT_AGE: Slot = 150;
+/// u8 amount of signatures + u8 padding
+const ED25519_HEADER_SIZE: usize = 2;
+/// seven u16 offsets and instruction indexes
+const ED25519_OFFSETS_SIZE: usize = 14;
+/// instruction index, which means data is stored in ed25519 instruction itself
+const ED25519_CURRENT_INSTRUCTION: u16 = u16::MAX;
+const ED25519_PUBKEY_SIZE: usize = 32;
+const ED25519_SIGNATURE_SIZE: usize = 64;
+/// lottery pubkey + u64 round + u64 draw slot
+const ORACLE_MESSAGE_SIZE: usize = 48;
 
-/// checking whether new participant can join the lottery
-pub fn check_for_lottery_availability(pda_account: &AccountInfo) -> ProgramResult {
-    msg!("Acc data: {:?}", &pda_account.data.borrow());
+impl LotteryAccount {
+    /// every lottery has its own PDA, derived from the authority, who created it, and lottery id
+    pub fn get_lottery_pubkey(program_id: &Pubkey, authority: &Pubkey, lottery_id: u64) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[LOTTERY_SEED.as_bytes(), authority.as_ref(), &lottery_id.to_le_bytes()],
+            program_id
+        )
+    }
+
+    /// token vault is derived from the lottery pubkey only, so it doesn't change when authority is handed over
+    /// vault is a token account, which is its own owner, so the program signs transfers with vault seeds
+    pub fn get_vault_pubkey(program_id: &Pubkey, lottery_pubkey: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[VAULT_SEED.as_bytes(), lottery_pubkey.as_ref()], program_id)
+    }
+
+    /// bets and prizes are SPL tokens instead of lamports
+    pub fn is_token_lottery(&self) -> bool {
+        self.mint != Pubkey::default()
+    }
+
+    pub fn lottery_state(&self) -> Result<LotteryState, ProgramError> {
+        LotteryState::from_u8(self.lottery_state).ok_or_else(|| LotteryError::InvalidLotteryAccount.into())
+    }
+
+    pub fn randomness_mode(&self) -> Result<RandomnessMode, ProgramError> {
+        RandomnessMode::from_u8(self.randomness_mode).ok_or_else(|| LotteryError::InvalidLotteryAccount.into())
+    }
 
-    let lottery_data: LotteryAccount = deserialize(&pda_account.data.borrow())?;
+    pub fn selection_mode(&self) -> Result<SelectionMode, ProgramError> {
+        SelectionMode::from_u8(self.selection_mode).ok_or_else(|| LotteryError::InvalidLotteryAccount.into())
+    }
 
-    msg!("Max participants in lottery: {:?}", lottery_data.max_participants);
+    pub fn storage_mode(&self) -> Result<StorageMode, ProgramError> {
+        StorageMode::from_u8(self.storage_mode).ok_or_else(|| LotteryError::InvalidLotteryAccount.into())
+    }
 
-    if lottery_data.lottery_state != LotteryState::IN_PROGRESS || 
-        lottery_data.participants.len() as u32 >= lottery_data.max_participants {
-        return Err(ProgramError::Custom(100));  
+    /// part of the cumulative index, that participant's entry covers
+    pub fn entry_weight(&self, lamports: u64, tickets: u64) -> Result<u64, ProgramError> {
+        if self.ticket_price > 0 {
+            return Ok(tickets);
+        }
+
+        match self.selection_mode()? {
+            SelectionMode::Uniform => Ok(1),
+            SelectionMode::Weighted => Ok(lamports),
+        }
     }
 
-    Ok(()) 
+    /// v2 header with every field of v1 header, fields added in v2 start empty
+    pub fn from_v1(v1: &LotteryAccountV1) -> Self {
+        LotteryAccount {
+            discriminator: LOTTERY_DISCRIMINATOR,
+            version: LOTTERY_VERSION,
+            winners: v1.winners,
+            authority: v1.authority,
+            treasury: v1.treasury,
+            pending_authority: v1.pending_authority,
+            oracle: v1.oracle,
+            mint: v1.mint,
+            commitment: v1.commitment,
+            draw_entropy: v1.draw_entropy,
+            lottery_start: v1.lottery_start,
+            lottery_end: v1.lottery_end,
+            lottery_id: v1.lottery_id,
+            reveal_deadline: v1.reveal_deadline,
+            ticket_price: v1.ticket_price,
+            min_bet: v1.min_bet,
+            max_bet: v1.max_bet,
+            total_tickets: v1.total_tickets,
+            round: v1.round,
+            total_weight: v1.total_weight,
+            total_lamports: v1.total_lamports,
+            max_participants: v1.max_participants,
+            participants_count: v1.participants_count,
+            fee_bps: v1.fee_bps,
+            lottery_state: v1.lottery_state,
+            randomness_mode: v1.randomness_mode,
+            selection_mode: v1.selection_mode,
+            winners_amount: v1.winners_amount,
+            prize_tiers: v1.prize_tiers,
+            storage_mode: v1.storage_mode,
+            reserved: [0; 3],
+            open_entries: 0,
+        }
+    }
 }
 
-impl LotteryAccount {
-    pub fn get_lottery_pubkey(program_id: &Pubkey) -> (Pubkey, u8) {
-        Pubkey::find_program_address(&[LOTTERY_SEED.as_bytes()], program_id)
+impl LotteryEntry {
+    /// every participant has one entry per lottery, it's reused in the next rounds
+    pub fn get_entry_pubkey(program_id: &Pubkey, lottery_pubkey: &Pubkey, participant: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[ENTRY_SEED.as_bytes(), lottery_pubkey.as_ref(), participant.as_ref()], program_id)
     }
+}
 
-    pub fn check_pubkey(program_id: &Pubkey, pubkey_to_check: &Pubkey) -> bool {
-        let (lottery_pubkey, _) = Self::get_lottery_pubkey(program_id);
-        lottery_pubkey.to_bytes() == pubkey_to_check.to_bytes()
+/// checking that passed lottery account is writable and owned by the program
+pub fn check_lottery_account(program_id: &Pubkey, pda_acc: &AccountInfo) -> ProgramResult {
+    if !pda_acc.is_writable || pda_acc.owner != program_id {
+        return Err(LotteryError::InvalidLotteryAccount.into());
     }
+
+    Ok(())
 }
 
-/// calculate size of acc
-pub fn calculate_lottery_account_size(max_participants: u32) -> u64 {
-    let mut size: u32 = 0;
-    size += size_of::<u32>() as u32;
-    size += size_of::<LotteryState>() as u32;
-    size += size_of::<Pubkey>() as u32;
-    size += size_of::<u64>() as u32;
-    size += max_participants * ((size_of::<Pubkey>() + size_of::<u64>()) as u32) + 4; // 4 bytes is for HashMap capacity - https://github.com/near/borsh-rs/blob/master/borsh/src/de/mod.rs, 393 line
-
-    size as u64
-}
-
-/// custom deserialization
-/// first 49 bytes - determined account data, then goes HashMap (key, value) pairs
-/// each pair - 40 bytes
-pub fn deserialize(input: &[u8]) -> Result<LotteryAccount, ProgramError> {
-    msg!("Input bytes {:?}", input);
-    let mut stopping_index = 0; 
-    let mut i = 0;
-    let empty_pubkey = Pubkey::new_from_array([0; 32]);
-    while i < input.len() {
-        if i > 48 {
-            // getting key of HashMap and checking whether it's empty
-            let byte_array: [u8; 32] = input[i..i + 32].try_into().unwrap();
-            let pubkey = Pubkey::new_from_array(byte_array);
-            
-            if pubkey == empty_pubkey {
-                stopping_index = i;
-                break;
-            } else {
-                // 32 pubkey + 8 u64 donation
-                i += 40;
+/// checking that lottery is managed by its authority
+pub fn check_authority(lottery_account: &LotteryAccount, authority_acc: &AccountInfo) -> ProgramResult {
+    if !authority_acc.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if *authority_acc.key != lottery_account.authority {
+        return Err(LotteryError::InvalidAuthority.into());
+    }
+
+    Ok(())
+}
+
+/// checking whether bets are still accepted by the time
+pub fn check_lottery_not_ended(lottery_account: &LotteryAccount, now: i64) -> ProgramResult {
+    if now >= lottery_account.lottery_end as i64 {
+        return Err(LotteryError::LotteryEnded.into());
+    }
+
+    Ok(())
+}
+
+/// checking that lottery uses either ticket price or min/max bet range, and that the range isn't empty
+pub fn check_bet_limits(ticket_price: u64, min_bet: u64, max_bet: u64) -> ProgramResult {
+    if ticket_price > 0 && (min_bet > 0 || max_bet > 0) {
+        return Err(LotteryError::InvalidBetLimits.into());
+    }
+
+    if max_bet > 0 && min_bet > max_bet {
+        return Err(LotteryError::InvalidBetLimits.into());
+    }
+
+    Ok(())
+}
+
+/// checking single bet against lottery's ticket price or min/max bet range
+pub fn check_bet_amount(lottery_account: &LotteryAccount, lamports_amount: u64) -> ProgramResult {
+    if lamports_amount == 0 {
+        return Err(LotteryError::InvalidBetAmount.into());
+    }
+
+    if lottery_account.ticket_price > 0 && lamports_amount.checked_rem(lottery_account.ticket_price) != Some(0) {
+        return Err(LotteryError::InvalidBetAmount.into());
+    }
+
+    if lamports_amount < lottery_account.min_bet ||
+        (lottery_account.max_bet > 0 && lamports_amount > lottery_account.max_bet) {
+        return Err(LotteryError::InvalidBetAmount.into());
+    }
+
+    Ok(())
+}
+
+/// token accounts of a lottery in SPL token mode, passed to instructions, that move funds
+pub struct TokenVault<'a, 'info> {
+    pub vault_acc: &'a AccountInfo<'info>,
+    pub token_program: &'a AccountInfo<'info>,
+    pub vault_bump: u8,
+}
+
+/// checking that passed program is SPL Token program
+pub fn check_token_program(token_program: &AccountInfo) -> ProgramResult {
+    if *token_program.key != spl_token::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    Ok(())
+}
+
+/// taking vault and token program accounts, lottery in lamports doesn't expect them
+pub fn next_token_vault<'a, 'info, I: Iterator<Item = &'a AccountInfo<'info>>>(
+    program_id: &Pubkey,
+    lottery_account: &LotteryAccount,
+    lottery_pubkey: &Pubkey,
+    accounts_iter: &mut I,
+) -> Result<Option<TokenVault<'a, 'info>>, ProgramError> {
+    if !lottery_account.is_token_lottery() {
+        return Ok(None);
+    }
+
+    let vault_acc = next_account_info(accounts_iter)?;
+    let token_program = next_account_info(accounts_iter)?;
+    check_token_program(token_program)?;
+
+    let (vault_pubkey, vault_bump) = LotteryAccount::get_vault_pubkey(program_id, lottery_pubkey);
+    if *vault_acc.key != vault_pubkey || !vault_acc.is_writable {
+        return Err(LotteryError::InvalidVault.into());
+    }
+
+    Ok(Some(TokenVault { vault_acc, token_program, vault_bump }))
+}
+
+/// reading token account, checking that it holds lottery's mint and returning its owner
+pub fn get_token_account_owner(token_acc: &AccountInfo, mint: &Pubkey) -> Result<Pubkey, ProgramError> {
+    if *token_acc.owner != spl_token::id() {
+        return Err(LotteryError::InvalidTokenAccount.into());
+    }
+
+    let token_account = spl_token::state::Account::unpack(&token_acc.try_borrow_data()?)
+        .map_err(|_| LotteryError::InvalidTokenAccount)?;
+    if token_account.mint != *mint {
+        return Err(LotteryError::InvalidTokenAccount.into());
+    }
+
+    Ok(token_account.owner)
+}
+
+/// creating token vault of the lottery on the first start, existing vault must hold the same mint
+pub fn open_token_vault<'info>(
+    program_id: &Pubkey,
+    payer_acc: &AccountInfo<'info>,
+    pda_acc: &AccountInfo<'info>,
+    vault_acc: &AccountInfo<'info>,
+    mint_acc: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> ProgramResult {
+    check_token_program(token_program)?;
+    if mint_acc.owner != token_program.key {
+        return Err(LotteryError::InvalidMint.into());
+    }
+
+    let (vault_pubkey, vault_bump) = LotteryAccount::get_vault_pubkey(program_id, pda_acc.key);
+    if *vault_acc.key != vault_pubkey || !vault_acc.is_writable {
+        return Err(LotteryError::InvalidVault.into());
+    }
+
+    if !vault_acc.data_is_empty() {
+        // vault of a restarted lottery can't switch to another token
+        return match get_token_account_owner(vault_acc, mint_acc.key) {
+            Ok(owner) if owner == vault_pubkey => Ok(()),
+            _ => Err(LotteryError::InvalidVault.into()),
+        };
+    }
+
+    msg!("Creating token vault {:?} for mint {:?}", vault_pubkey, mint_acc.key);
+
+    let space = spl_token::state::Account::LEN;
+    let vault_seeds: &[&[_]] = &[VAULT_SEED.as_bytes(), pda_acc.key.as_ref(), &[vault_bump]];
+
+    create_pda_account(payer_acc, vault_acc, system_program, &Rent::get()?, space, token_program.key, vault_seeds)?;
+
+    invoke_signed(
+        &spl_token::instruction::initialize_account3(token_program.key, &vault_pubkey, mint_acc.key, &vault_pubkey)?,
+        &[vault_acc.clone(), mint_acc.clone(), token_program.clone()],
+        &[vault_seeds]
+    )
+}
+
+/// creating account at a PDA, signed with its seeds
+/// anyone can send lamports to the address beforehand, create_account fails then,
+/// so such account is topped up to rent exemption, allocated and assigned instead
+pub fn create_pda_account<'info>(
+    payer_acc: &AccountInfo<'info>,
+    new_acc: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    rent: &Rent,
+    space: usize,
+    owner: &Pubkey,
+    seeds: &[&[u8]],
+) -> ProgramResult {
+    let lamports = rent.minimum_balance(space);
+
+    if new_acc.lamports() == 0 {
+        return invoke_signed(
+            &system_instruction::create_account(payer_acc.key, new_acc.key, lamports, space as u64, owner),
+            &[payer_acc.clone(), new_acc.clone(), system_program.clone()],
+            &[seeds]
+        );
+    }
+
+    msg!("Account {:?} is already funded, allocating it in place", new_acc.key);
+
+    let shortfall = lamports.saturating_sub(new_acc.lamports());
+    if shortfall > 0 {
+        invoke(
+            &system_instruction::transfer(payer_acc.key, new_acc.key, shortfall),
+            &[payer_acc.clone(), new_acc.clone(), system_program.clone()]
+        )?;
+    }
+
+    invoke_signed(
+        &system_instruction::allocate(new_acc.key, space as u64),
+        &[new_acc.clone(), system_program.clone()],
+        &[seeds]
+    )?;
+
+    invoke_signed(
+        &system_instruction::assign(new_acc.key, owner),
+        &[new_acc.clone(), system_program.clone()],
+        &[seeds]
+    )
+}
+
+/// moving funds out of the lottery, lamports are debited from PDA directly, tokens are sent from the vault
+pub fn transfer_from_lottery<'info>(
+    pda_acc: &AccountInfo<'info>,
+    token_vault: Option<&TokenVault<'_, 'info>>,
+    destination_acc: &AccountInfo<'info>,
+    amount: u64,
+) -> ProgramResult {
+    match token_vault {
+        Some(token_vault) => {
+            let vault_seeds: &[&[_]] = &[VAULT_SEED.as_bytes(), pda_acc.key.as_ref(), &[token_vault.vault_bump]];
+
+            invoke_signed(
+                &spl_token::instruction::transfer(
+                    token_vault.token_program.key,
+                    token_vault.vault_acc.key,
+                    destination_acc.key,
+                    token_vault.vault_acc.key,
+                    &[],
+                    amount
+                )?,
+                &[token_vault.vault_acc.clone(), destination_acc.clone(), token_vault.token_program.clone()],
+                &[vault_seeds]
+            )
+        }
+        None => {
+            if **pda_acc.try_borrow_lamports()? < amount {
+                return Err(LotteryError::InsufficientFunds.into());
             }
-        } else {
-            i += 1;
+
+            **pda_acc.try_borrow_mut_lamports()? -= amount;
+            **destination_acc.try_borrow_mut_lamports()? += amount;
+
+            Ok(())
         }
     }
+}
+
+/// closing token vault of the lottery, its rent goes to destination account
+/// caller makes sure nothing is owed anymore, tokens left in the vault are swept to destination's token account
+pub fn close_token_vault<'info>(
+    pda_acc: &AccountInfo<'info>,
+    token_vault: &TokenVault<'_, 'info>,
+    destination_acc: &AccountInfo<'info>,
+    destination_token_acc: &AccountInfo<'info>,
+) -> ProgramResult {
+    let vault_account = spl_token::state::Account::unpack(&token_vault.vault_acc.try_borrow_data()?)
+        .map_err(|_| LotteryError::InvalidVault)?;
 
-    let lottery_account: LotteryAccount;
-    msg!("Stopping index {}", stopping_index);
-    if stopping_index == 0 {
-        lottery_account = LotteryAccount::try_from_slice(&input)?;
-    } else {
-        lottery_account = LotteryAccount::try_from_slice(&input[..stopping_index])?;
+    if vault_account.amount > 0 {
+        if get_token_account_owner(destination_token_acc, &vault_account.mint)? != *destination_acc.key || !destination_token_acc.is_writable {
+            return Err(LotteryError::InvalidTokenAccount.into());
+        }
+
+        msg!("Sweeping {} tokens, which aren't owed to anyone, to {:?}", vault_account.amount, destination_token_acc.key);
+        transfer_from_lottery(pda_acc, Some(token_vault), destination_token_acc, vault_account.amount)?;
     }
-     
-    Ok(lottery_account)
+
+    let vault_seeds: &[&[_]] = &[VAULT_SEED.as_bytes(), pda_acc.key.as_ref(), &[token_vault.vault_bump]];
+
+    invoke_signed(
+        &spl_token::instruction::close_account(
+            token_vault.token_program.key,
+            token_vault.vault_acc.key,
+            destination_acc.key,
+            token_vault.vault_acc.key,
+            &[]
+        )?,
+        &[token_vault.vault_acc.clone(), destination_acc.clone(), token_vault.token_program.clone()],
+        &[vault_seeds]
+    )
+}
+
+/// creating participant's entry on his first bet in the lottery, existing entry is left as it is
+/// returns whether the entry was created
+pub fn open_entry<'info>(
+    program_id: &Pubkey,
+    participant_acc: &AccountInfo<'info>,
+    pda_acc: &AccountInfo<'info>,
+    entry_acc: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<bool, ProgramError> {
+    let (entry_pubkey, entry_bump) = LotteryEntry::get_entry_pubkey(program_id, pda_acc.key, participant_acc.key);
+    if *entry_acc.key != entry_pubkey || !entry_acc.is_writable {
+        return Err(LotteryError::InvalidEntry.into());
+    }
+
+    if !entry_acc.data_is_empty() {
+        return Ok(false);
+    }
+
+    msg!("Creating entry {:?} for participant {:?}", entry_pubkey, participant_acc.key);
+
+    let space = size_of::<LotteryEntry>();
+    let entry_seeds: &[&[_]] = &[ENTRY_SEED.as_bytes(), pda_acc.key.as_ref(), participant_acc.key.as_ref(), &[entry_bump]];
+
+    create_pda_account(participant_acc, entry_acc, system_program, &Rent::get()?, space, program_id, entry_seeds)?;
+
+    let mut entry_data = entry_acc.try_borrow_mut_data()?;
+    let entry: &mut LotteryEntry = bytemuck::try_from_bytes_mut(&mut entry_data)
+        .map_err(|_| LotteryError::InvalidEntry)?;
+    entry.lottery = *pda_acc.key;
+    entry.participant = *participant_acc.key;
+    entry.bump = entry_bump;
+
+    Ok(true)
+}
+
+/// wiping program account and handing it back to system program, all its lamports go to destination
+/// account can't be revived with the old data within the transaction
+pub fn close_program_account(acc: &AccountInfo, destination_acc: &AccountInfo) -> Result<u64, ProgramError> {
+    acc.try_borrow_mut_data()?.fill(0);
+    acc.realloc(0, false)?;
+    acc.assign(&system_program::id());
+
+    let lamports = acc.lamports();
+    **acc.try_borrow_mut_lamports()? = 0;
+    **destination_acc.try_borrow_mut_lamports()? = destination_acc.lamports().checked_add(lamports).ok_or(LotteryError::ArithmeticOverflow)?;
+
+    Ok(lamports)
+}
+
+/// checking that entry is the PDA of the lottery and its participant
+fn check_entry(program_id: &Pubkey, lottery_pubkey: &Pubkey, entry_acc: &AccountInfo, entry: &LotteryEntry) -> ProgramResult {
+    let entry_pubkey = Pubkey::create_program_address(
+        &[ENTRY_SEED.as_bytes(), lottery_pubkey.as_ref(), entry.participant.as_ref(), &[entry.bump]],
+        program_id
+    ).map_err(|_| LotteryError::InvalidEntry)?;
+
+    if entry.lottery != *lottery_pubkey || entry_pubkey != *entry_acc.key {
+        return Err(LotteryError::InvalidEntry.into());
+    }
+
+    Ok(())
+}
+
+/// reading participant's entry in place
+pub fn load_entry<'a>(program_id: &Pubkey, lottery_pubkey: &Pubkey, entry_acc: &AccountInfo, data: &'a [u8]) -> Result<&'a LotteryEntry, ProgramError> {
+    if entry_acc.owner != program_id {
+        return Err(LotteryError::InvalidEntry.into());
+    }
+
+    let entry: &LotteryEntry = bytemuck::try_from_bytes(data).map_err(|_| LotteryError::InvalidEntry)?;
+    check_entry(program_id, lottery_pubkey, entry_acc, entry)?;
+
+    Ok(entry)
+}
+
+/// same as load_entry, but for writing
+pub fn load_entry_mut<'a>(program_id: &Pubkey, lottery_pubkey: &Pubkey, entry_acc: &AccountInfo, data: &'a mut [u8]) -> Result<&'a mut LotteryEntry, ProgramError> {
+    if entry_acc.owner != program_id || !entry_acc.is_writable {
+        return Err(LotteryError::InvalidEntry.into());
+    }
+
+    let entry: &mut LotteryEntry = bytemuck::try_from_bytes_mut(data).map_err(|_| LotteryError::InvalidEntry)?;
+    check_entry(program_id, lottery_pubkey, entry_acc, entry)?;
+
+    Ok(entry)
+}
+
+/// counting entry PDAs of the lottery, every entry must be passed once
+pub fn count_entries<'a, 'info: 'a>(
+    program_id: &Pubkey,
+    lottery_pubkey: &Pubkey,
+    entry_accs: impl Iterator<Item = &'a AccountInfo<'info>>,
+) -> Result<u32, ProgramError> {
+    let mut entries: Vec<Pubkey> = Vec::new();
+    for entry_acc in entry_accs {
+        let entry_data = entry_acc.try_borrow_data()?;
+        load_entry(program_id, lottery_pubkey, entry_acc, &entry_data)?;
+        entries.push(*entry_acc.key);
+    }
+
+    let passed = entries.len();
+    entries.sort_unstable();
+    entries.dedup();
+    if entries.len() != passed {
+        return Err(LotteryError::InvalidEntry.into());
+    }
+
+    u32::try_from(passed).map_err(|_| LotteryError::ArithmeticOverflow.into())
+}
+
+/// taking participant's bet out of his entry, entry without lamports can't win
+/// the last range of the cumulative index is given back, ranges in the middle stay and the draw steps over them
+pub fn take_entry_bet(program_id: &Pubkey, lottery_account: &mut LotteryAccount, lottery_pubkey: &Pubkey, entry_acc: &AccountInfo, participant: &Pubkey) -> Result<u64, ProgramError> {
+    let mut entry_data = entry_acc.try_borrow_mut_data()?;
+    let entry = load_entry_mut(program_id, lottery_pubkey, entry_acc, &mut entry_data)?;
+
+    if entry.participant != *participant || entry.round != lottery_account.round || entry.lamports == 0 {
+        return Err(LotteryError::InvalidParticipantAccount.into());
+    }
+
+    let bet = entry.lamports;
+    if entry.start + entry.weight == lottery_account.total_weight {
+        lottery_account.total_weight = entry.start;
+        entry.weight = 0;
+    }
+
+    lottery_account.total_lamports = lottery_account.total_lamports.checked_sub(bet).ok_or(LotteryError::ArithmeticOverflow)?;
+    lottery_account.total_tickets = lottery_account.total_tickets.checked_sub(entry.tickets).ok_or(LotteryError::ArithmeticOverflow)?;
+    lottery_account.participants_count = lottery_account.participants_count.checked_sub(1).ok_or(LotteryError::ArithmeticOverflow)?;
+    entry.lamports = 0;
+    entry.tickets = 0;
+
+    Ok(bet)
+}
+
+/// calculate size of acc, header and a slot per participant
+pub fn calculate_lottery_account_size(max_participants: u32) -> u64 {
+    (size_of::<LotteryAccount>() + max_participants as usize * size_of::<ParticipantBet>()) as u64
+}
+
+/// growing lottery account in place, so it fits a slot per participant, existing data is kept and new slots are zeroed
+/// one instruction adds up to MAX_PERMITTED_DATA_INCREASE / 56, about 182 slots, bigger lotteries are grown in several calls
+pub fn expand_lottery_account<'info>(
+    payer_acc: &AccountInfo<'info>,
+    pda_acc: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    max_participants: u32,
+) -> ProgramResult {
+    grow_lottery_account(payer_acc, pda_acc, system_program, calculate_lottery_account_size(max_participants) as usize)
+}
+
+/// reallocating lottery account to a bigger size, account, which is big enough already, isn't changed
+/// payer covers rent of the added bytes only, lamports of the pot aren't counted as rent
+pub fn grow_lottery_account<'info>(
+    payer_acc: &AccountInfo<'info>,
+    pda_acc: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    space: usize,
+) -> ProgramResult {
+    let current_space = pda_acc.data_len();
+    if space <= current_space {
+        return Ok(());
+    }
+
+    // runtime limits how much an account can grow in one instruction
+    if space - current_space > MAX_PERMITTED_DATA_INCREASE {
+        return Err(LotteryError::GrowthLimitExceeded.into());
+    }
+
+    msg!("Growing lottery account from {} to {} bytes", current_space, space);
+
+    let rent = Rent::get()?;
+    let lamports = rent.minimum_balance(space).saturating_sub(rent.minimum_balance(current_space));
+    invoke(
+        &system_instruction::transfer(payer_acc.key, pda_acc.key, lamports),
+        &[payer_acc.clone(), pda_acc.clone(), system_program.clone()]
+    )?;
+
+    pda_acc.realloc(space, true)
+}
+
+/// writing discriminator and current version into a freshly created lottery account
+pub fn init_lottery_account(data: &mut [u8]) -> ProgramResult {
+    if data.len() < size_of::<LotteryAccount>() {
+        return Err(LotteryError::InvalidLotteryAccount.into());
+    }
+
+    data[..LOTTERY_DISCRIMINATOR.len()].copy_from_slice(&LOTTERY_DISCRIMINATOR);
+    data[LOTTERY_DISCRIMINATOR.len()] = LOTTERY_VERSION;
+
+    Ok(())
+}
+
+/// reading layout version of lottery account
+/// v1 accounts had no discriminator, they are recognized by size, header and whole participant slots
+pub fn get_lottery_version(data: &[u8]) -> Result<u8, ProgramError> {
+    if data.starts_with(&LOTTERY_DISCRIMINATOR) {
+        return data.get(LOTTERY_DISCRIMINATOR.len()).copied().ok_or(LotteryError::InvalidLotteryAccount.into());
+    }
+
+    if data.len() >= LOTTERY_V1_HEADER_SIZE && (data.len() - LOTTERY_V1_HEADER_SIZE).is_multiple_of(size_of::<ParticipantBet>()) {
+        return Ok(1);
+    }
+
+    Err(LotteryError::InvalidLotteryAccount.into())
+}
+
+/// checking that lottery account has the current layout, handlers can't read any other
+fn check_lottery_version(data: &[u8]) -> ProgramResult {
+    if get_lottery_version(data)? != LOTTERY_VERSION {
+        return Err(LotteryError::UnsupportedVersion.into());
+    }
+
+    Ok(())
+}
+
+/// participants count is trusted only when it fits max participants and slots of the account
+fn check_participants_count(lottery_account: &LotteryAccount, slots_amount: usize) -> ProgramResult {
+    // participants of a lottery in Entries mode are kept in their own accounts
+    if lottery_account.storage_mode()? == StorageMode::Entries {
+        return Ok(());
+    }
+
+    let participants_count = lottery_account.participants_count as usize;
+    if participants_count > lottery_account.max_participants as usize || participants_count > slots_amount {
+        return Err(LotteryError::InvalidParticipantsCount.into());
+    }
+
+    Ok(())
+}
+
+/// reading lottery account in place, nothing is decoded or copied
+/// returns lottery header and participants, who have already donated, slots after them are ignored
+pub fn load_lottery(data: &[u8]) -> Result<(&LotteryAccount, &[ParticipantBet]), ProgramError> {
+    check_lottery_version(data)?;
+    if data.len() < size_of::<LotteryAccount>() {
+        return Err(LotteryError::InvalidLotteryAccount.into());
+    }
+
+    let (header_bytes, slots_bytes) = data.split_at(size_of::<LotteryAccount>());
+    let lottery_account: &LotteryAccount = bytemuck::try_from_bytes(header_bytes)
+        .map_err(|_| LotteryError::InvalidLotteryAccount)?;
+
+    check_participants_count(lottery_account, slots_bytes.len() / size_of::<ParticipantBet>())?;
+
+    let participants_count = match lottery_account.storage_mode()? {
+        StorageMode::Slots => lottery_account.participants_count as usize,
+        StorageMode::Entries => 0,
+    };
+    let participants: &[ParticipantBet] = bytemuck::try_cast_slice(&slots_bytes[..participants_count * size_of::<ParticipantBet>()])
+        .map_err(|_| LotteryError::InvalidLotteryAccount)?;
+
+    Ok((lottery_account, participants))
+}
+
+/// same as load_lottery, but for writing
+/// returns all participant slots, used ones go first, so new participants can be inserted in place
+pub fn load_lottery_mut(data: &mut [u8]) -> Result<(&mut LotteryAccount, &mut [ParticipantBet]), ProgramError> {
+    check_lottery_version(data)?;
+    if data.len() < size_of::<LotteryAccount>() {
+        return Err(LotteryError::InvalidLotteryAccount.into());
+    }
+
+    let (header_bytes, slots_bytes) = data.split_at_mut(size_of::<LotteryAccount>());
+    let lottery_account: &mut LotteryAccount = bytemuck::try_from_bytes_mut(header_bytes)
+        .map_err(|_| LotteryError::InvalidLotteryAccount)?;
+
+    let slots_len = slots_bytes.len() / size_of::<ParticipantBet>() * size_of::<ParticipantBet>();
+    let participants: &mut [ParticipantBet] = bytemuck::try_cast_slice_mut(&mut slots_bytes[..slots_len])
+        .map_err(|_| LotteryError::InvalidLotteryAccount)?;
+
+    check_participants_count(lottery_account, participants.len())?;
+
+    Ok((lottery_account, participants))
+}
+
+/// binary search of participant among used slots, Err holds the index to insert him at
+pub fn find_participant(lottery_account: &LotteryAccount, participants: &[ParticipantBet], pubkey: &Pubkey) -> Result<usize, usize> {
+    participants[..lottery_account.participants_count as usize].binary_search_by(|bet| bet.pubkey.cmp(pubkey))
+}
+
+/// inserting participant into used slots, only slots after him are shifted
+pub fn insert_participant(lottery_account: &mut LotteryAccount, participants: &mut [ParticipantBet], index: usize, bet: ParticipantBet) -> ProgramResult {
+    let count = lottery_account.participants_count as usize;
+    if count >= participants.len() {
+        return Err(LotteryError::LotteryIsFull.into());
+    }
+
+    participants.copy_within(index..count, index + 1);
+    participants[index] = bet;
+    lottery_account.participants_count += 1;
+
+    Ok(())
+}
+
+/// removing participant from used slots, freed slot at the end is zeroed
+pub fn remove_participant(lottery_account: &mut LotteryAccount, participants: &mut [ParticipantBet], index: usize) -> ParticipantBet {
+    let count = lottery_account.participants_count as usize;
+    let bet = participants[index];
+
+    participants.copy_within(index + 1..count, index);
+    participants[count - 1] = ParticipantBet::default();
+    lottery_account.participants_count -= 1;
+
+    bet
 }
 
 /// calculate overall amount of lampotrs, that has been donated
-pub fn calculate_overall_donations(lottery_account: &LotteryAccount) -> Option<u64> {
+pub fn calculate_overall_donations(participants: &[ParticipantBet]) -> Option<u64> {
     let mut sum: u64 = 0;
-    for (_, bet) in  lottery_account.participants.iter() {
-        sum += bet.clone();
+    for bet in participants {
+        sum = sum.checked_add(bet.lamports)?;
     }
 
     if sum > 0 {
@@ -102,109 +730,376 @@ pub fn calculate_overall_donations(lottery_account: &LotteryAccount) -> Option<u
     Option::None
 }
 
+/// recalculating ticket ranges after participant at from_index has changed, ranges before him stay the same
+/// ranges go one after another in key order, so ticket number maps to the same owner on-chain and off-chain
+pub fn update_ticket_ranges(lottery_account: &mut LotteryAccount, participants: &mut [ParticipantBet], from_index: usize) -> ProgramResult {
+    if lottery_account.ticket_price == 0 {
+        return Ok(());
+    }
+
+    let count = lottery_account.participants_count as usize;
+    let mut total_tickets: u64 = match from_index.checked_sub(1) {
+        Some(previous) => participants[previous].first_ticket + participants[previous].tickets,
+        None => 0,
+    };
+    for bet in participants[from_index.min(count)..count].iter_mut() {
+        bet.first_ticket = total_tickets;
+        total_tickets = total_tickets
+            .checked_add(bet.tickets)
+            .ok_or(LotteryError::ArithmeticOverflow)?;
+    }
+
+    lottery_account.total_tickets = total_tickets;
+
+    Ok(())
+}
+
+/// calculate house fee from overall donations and fee in basis points
+///
+/// Fee is rounded down, so the rounding always goes in favour of the winners.
+/// E.g. with 1% fee 1_999 lamports of donations give 19 lamports of fee, not 19.99:
+///
+/// ```
+/// use lottery::utils::calculate_fee;
+///
+/// assert_eq!(calculate_fee(1_999, 100), Ok(19));
+/// assert_eq!(calculate_fee(99, 100), Ok(0));
+/// assert_eq!(calculate_fee(10_000, 250), Ok(250));
+/// assert_eq!(calculate_fee(u64::MAX, 1_000), Ok(u64::MAX / 10));
+/// ```
+pub fn calculate_fee(overall_donations: u64, fee_bps: u16) -> Result<u64, ProgramError> {
+    if fee_bps > MAX_FEE_BPS {
+        return Err(LotteryError::InvalidFee.into());
+    }
+
+    // u128 can't overflow here, but checked math keeps the money path explicit
+    let fee = (overall_donations as u128)
+        .checked_mul(fee_bps as u128)
+        .and_then(|product| product.checked_div(BPS_DENOMINATOR as u128))
+        .ok_or(LotteryError::ArithmeticOverflow)?;
+
+    u64::try_from(fee).map_err(|_| LotteryError::ArithmeticOverflow.into())
+}
+
 /// checking if instructions are called in correct order
 pub fn check_lottery_lifecycle(instruction_code: u32, lottery_account: Option<&LotteryAccount>) -> ProgramResult {
     match instruction_code {
         0 => {
-            if lottery_account.is_some() {
-                let unwrapped = lottery_account.unwrap();
-                if (unwrapped.lottery_state == LotteryState::IN_PROGRESS) ||  (unwrapped.lottery_state == LotteryState::LAUCNHED) {
-                     return Err(ProgramError::Custom(300));
+            if let Some(unwrapped) = lottery_account {
+                let lottery_state = unwrapped.lottery_state()?;
+                // lottery can be started again only after its bets are paid out or refunded
+                if (lottery_state == LotteryState::IN_PROGRESS) || (lottery_state == LotteryState::BETS_CLOSED) || (lottery_state == LotteryState::LAUCNHED) {
+                     return Err(LotteryError::WrongLifecycleState.into());
                 }
             }
 
-            return Ok(());
+            Ok(())
         }
-        1 | 2 | 3 => {
-            if lottery_account.is_none() { return Err(ProgramError::Custom(300)) }
+        1..=3 | 8..=11 | 13 => {
+            let unwrapped = match lottery_account {
+                Some(unwrapped) => unwrapped,
+                None => return Err(LotteryError::WrongLifecycleState.into()),
+            };
+            let lottery_state = unwrapped.lottery_state()?;
 
-            let unwrapped = lottery_account.unwrap();
             match instruction_code {
-                1 => { if unwrapped.lottery_state != LotteryState::IN_PROGRESS { return Err(ProgramError::Custom(300)) } }
-                2 => { 
-                    if unwrapped.lottery_state != LotteryState::IN_PROGRESS && unwrapped.lottery_state != LotteryState::BETS_CLOSED {
-                        return Err(ProgramError::Custom(300)) 
-                    } 
+                1 => { if lottery_state != LotteryState::IN_PROGRESS { return Err(LotteryError::WrongLifecycleState.into()) } }
+                2 => {
+                    if lottery_state != LotteryState::IN_PROGRESS && lottery_state != LotteryState::BETS_CLOSED {
+                        return Err(LotteryError::WrongLifecycleState.into())
+                    }
                 }
-                3 => { if unwrapped.lottery_state != LotteryState::LAUCNHED { return Err(ProgramError::Custom(300)) } }
-                _ => return Err(ProgramError::Custom(300))
+                3 => { if lottery_state != LotteryState::LAUCNHED { return Err(LotteryError::WrongLifecycleState.into()) } }
+                8 => {
+                    // winners are known after launch, cancelling then would let the operator veto the draw
+                    if lottery_state != LotteryState::IN_PROGRESS && lottery_state != LotteryState::BETS_CLOSED {
+                        return Err(LotteryError::WrongLifecycleState.into())
+                    }
+                }
+                9 => { if lottery_state != LotteryState::CANCELLED { return Err(LotteryError::WrongLifecycleState.into()) } }
+                10 => {
+                    if lottery_state != LotteryState::IN_PROGRESS && lottery_state != LotteryState::BETS_CLOSED {
+                        return Err(LotteryError::WrongLifecycleState.into())
+                    }
+                }
+                11 => {
+                    if lottery_state != LotteryState::LAUCNHED || unwrapped.winners[0] != Pubkey::default() {
+                        return Err(LotteryError::WrongLifecycleState.into())
+                    }
+                }
+                13 => {
+                    if lottery_state != LotteryState::COMPLETED && lottery_state != LotteryState::CANCELLED {
+                        return Err(LotteryError::WrongLifecycleState.into())
+                    }
+                }
+                _ => return Err(LotteryError::WrongLifecycleState.into())
             }
 
-            return Ok(());
+            Ok(())
         }
-        _ => return Err(ProgramError::Custom(300))
+        _ => Err(LotteryError::WrongLifecycleState.into())
+    }
+}
+
+/// reading the most recent entries of SlotHashes sysvar and hashing them into entropy
+/// sysvar data layout: u64 amount of entries, then (u64 slot, 32 bytes hash) per entry, most recent first
+pub fn get_slot_hashes_entropy(slot_hashes_acc: &AccountInfo) -> Result<Hash, ProgramError> {
+    if *slot_hashes_acc.key != slot_hashes::id() {
+        return Err(LotteryError::InvalidSlotHashesAccount.into());
+    }
+
+    let data = slot_hashes_acc.try_borrow_data()?;
+    let entries_amount = data
+        .get(..SLOT_HASHES_LEN_SIZE)
+        .and_then(|len_bytes| len_bytes.try_into().ok())
+        .map(u64::from_le_bytes)
+        .ok_or(LotteryError::InvalidSlotHashesAccount)? as usize;
+    let entries_to_read = entries_amount.min(SLOT_HASHES_ENTRIES_NUM);
+    if entries_to_read == 0 {
+        return Err(LotteryError::InvalidSlotHashesAccount.into());
+    }
+
+    let entries_end = SLOT_HASHES_LEN_SIZE + entries_to_read * SLOT_HASHES_ENTRY_SIZE;
+    let entries = data
+        .get(SLOT_HASHES_LEN_SIZE..entries_end)
+        .ok_or(LotteryError::InvalidSlotHashesAccount)?;
+
+    msg!("Read {} recent slot hashes", entries_to_read);
+
+    Ok(hashv(&[entries]))
+}
+
+/// checking operator's revealed seed against the commitment, stored at lottery start
+pub fn check_reveal(lottery_account: &LotteryAccount, reveal: &[u8; 32], now: i64) -> ProgramResult {
+    if now > lottery_account.reveal_deadline as i64 {
+        return Err(LotteryError::RevealDeadlinePassed.into());
+    }
+
+    if hashv(&[reveal]).to_bytes() != lottery_account.commitment {
+        return Err(LotteryError::InvalidReveal.into());
     }
 
     Ok(())
 }
 
-/// used to calculate random winner number from last 5 blockhashes of solana network
-pub fn calculate_random_number(input: &[u8], pool_size: u32) -> usize {
-    let general_len = input.len();
-    let hashes_amount = (general_len as f32 / 16.0) as usize; 
-    let single_len = general_len / if (hashes_amount) > 0 { hashes_amount } else { 1 };
-    let mut sum: u128 = 0;
-    let mut start = 0;
-    let mut end = 0;
-
-    for i in 0..hashes_amount {
-        start = single_len * i;
-        end = single_len * (i + 1);
-        let current_slice: &[u8];
-        if input.len() >= end {
-            current_slice = &input[start..end];
-        } else {
-            current_slice = &input[start..];
+/// checking ed25519 instruction, that goes right before the current one, and hashing oracle's signature into entropy
+/// the ed25519 program has already verified the signature, here we check who signed what
+/// signed message: 32 bytes lottery pubkey + u64 round + u64 draw slot, both little endian
+/// pubkey and round bind the signature to a single draw, so it can't be reused by another lottery or a restarted one
+/// the oracle signs any slot the authority asks for, so the authority can choose among signatures of the last MAX_DRAW_SLOT_AGE slots
+pub fn get_oracle_entropy(instructions_acc: &AccountInfo, lottery_account: &LotteryAccount, lottery_key: &Pubkey, current_slot: Slot) -> Result<Hash, ProgramError> {
+    if *instructions_acc.key != instructions::id() {
+        return Err(LotteryError::InvalidInstructionsSysvar.into());
+    }
+
+    let ed25519_ix = instructions::get_instruction_relative(-1, instructions_acc)
+        .map_err(|_| LotteryError::InvalidOracleInstruction)?;
+    if ed25519_ix.program_id != ed25519_program::id() {
+        return Err(LotteryError::InvalidOracleInstruction.into());
+    }
+
+    let data = &ed25519_ix.data;
+    if data.len() < ED25519_HEADER_SIZE + ED25519_OFFSETS_SIZE || data[0] != 1 {
+        return Err(LotteryError::InvalidOracleInstruction.into());
+    }
+
+    let offsets: Vec<u16> = data[ED25519_HEADER_SIZE..ED25519_HEADER_SIZE + ED25519_OFFSETS_SIZE]
+        .chunks_exact(2)
+        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+        .collect();
+    let (signature_offset, signature_ix, pubkey_offset, pubkey_ix, message_offset, message_size, message_ix) =
+        (offsets[0], offsets[1], offsets[2], offsets[3], offsets[4], offsets[5], offsets[6]);
+
+    // signature, pubkey and message must live in the ed25519 instruction, not somewhere else in the transaction
+    if signature_ix != ED25519_CURRENT_INSTRUCTION || pubkey_ix != ED25519_CURRENT_INSTRUCTION || message_ix != ED25519_CURRENT_INSTRUCTION {
+        return Err(LotteryError::InvalidOracleInstruction.into());
+    }
+
+    let read = |offset: u16, size: usize| -> Result<&[u8], ProgramError> {
+        data.get(offset as usize..offset as usize + size)
+            .ok_or_else(|| LotteryError::InvalidOracleInstruction.into())
+    };
+
+    let signer = read(pubkey_offset, ED25519_PUBKEY_SIZE)?;
+    if signer != lottery_account.oracle.as_ref() {
+        return Err(LotteryError::OracleSignerMismatch.into());
+    }
+
+    if message_size as usize != ORACLE_MESSAGE_SIZE {
+        return Err(LotteryError::InvalidOracleMessage.into());
+    }
+
+    let message = read(message_offset, ORACLE_MESSAGE_SIZE)?;
+    let mut round_bytes = [0u8; 8];
+    round_bytes.copy_from_slice(&message[32..40]);
+    let mut draw_slot_bytes = [0u8; 8];
+    draw_slot_bytes.copy_from_slice(&message[40..]);
+    let draw_slot = u64::from_le_bytes(draw_slot_bytes);
+
+    if &message[..32] != lottery_key.as_ref() || u64::from_le_bytes(round_bytes) != lottery_account.round ||
+        draw_slot > current_slot || current_slot - draw_slot > MAX_DRAW_SLOT_AGE {
+        return Err(LotteryError::InvalidOracleMessage.into());
+    }
+
+    let signature = read(signature_offset, ED25519_SIGNATURE_SIZE)?;
+
+    msg!("Oracle signature for draw slot {} is verified", draw_slot);
+
+    Ok(hashv(&[signature]))
+}
+
+/// used to calculate random winner number from on-chain entropy
+/// lottery pubkey is mixed in, so lotteries launched in the same slot get different numbers
+pub fn calculate_random_number(entropy: &Hash, lottery_pubkey: &Pubkey, pool_size: u64) -> u64 {
+    let seed = hashv(&[entropy.as_ref(), lottery_pubkey.as_ref()]).to_bytes();
+    let mut number_bytes = [0u8; 8];
+    number_bytes.copy_from_slice(&seed[..8]);
+
+    u64::from_le_bytes(number_bytes) % pool_size
+}
+
+/// checking that prize tiers are non-zero percents, that add up to 100
+pub fn check_prize_tiers(prize_tiers: &[u8]) -> ProgramResult {
+    if prize_tiers.is_empty() || prize_tiers.len() > MAX_WINNERS {
+        return Err(LotteryError::InvalidPrizeTiers.into());
+    }
+
+    let mut sum: u32 = 0;
+    for tier in prize_tiers {
+        if *tier == 0 {
+            return Err(LotteryError::InvalidPrizeTiers.into());
         }
+        sum += *tier as u32;
+    }
+
+    if sum != 100 {
+        return Err(LotteryError::InvalidPrizeTiers.into());
+    }
+
+    Ok(())
+}
 
-        let batch_size: usize = 4;
-        if current_slice.len() < 16 {
+/// choosing distinct winners, one per prize tier, according to lottery's selection mode
+/// participants are walked in key order and every tier uses hash of entropy and tier index,
+/// so the result can be recomputed from account data
+///
+/// In lotteries with tickets the draw picks a ticket number from 0 to total tickets and its owner wins,
+/// selection mode is ignored there. Tickets of earlier winners are left out of the next tiers,
+/// so the remaining ranges are renumbered in the same key order.
+pub fn select_winners(lottery_account: &LotteryAccount, participants: &[ParticipantBet], entropy: &Hash, lottery_pubkey: &Pubkey) -> Result<Vec<Pubkey>, ProgramError> {
+    let winners_amount = lottery_account.winners_amount as usize;
+    if participants.len() < winners_amount {
+        return Err(LotteryError::NotEnoughParticipants.into());
+    }
 
-            let difference = current_slice.len() - 16;
-            let extra_bytes: Vec<u8> = vec![0, difference.try_into().unwrap()];
-            let new_slice: Result<[u8; 16], _> = [current_slice, &extra_bytes].concat().try_into();
+    let selection_mode = lottery_account.selection_mode()?;
+    // participants, who haven't won yet
+    let mut pool: Vec<ParticipantBet> = participants.to_vec();
+    let mut winners: Vec<Pubkey> = Vec::with_capacity(winners_amount);
 
-            if new_slice.is_ok() {
-                sum += u128::from_le_bytes(new_slice.unwrap()) % (pool_size as u128);
+    for tier in 0..winners_amount {
+        let tier_entropy = hashv(&[entropy.as_ref(), &[tier as u8]]);
+        let winner_index = if lottery_account.ticket_price > 0 {
+            let pool_tickets = calculate_pool_weight(&pool, |bet| bet.tickets)?;
+            if pool_tickets == 0 {
+                return Err(LotteryError::NoDonations.into());
             }
-        } 
-
-        if current_slice.len() >= 16 {
-            let mut intervals_remain = current_slice.len() - 16;
-            let interval_size = if ((current_slice.len() - 16) / 3) >= 1 {(current_slice.len() - 16) / 3} else { 1 };
-            let mut byte_vec: Vec<u8> = current_slice[..batch_size].to_vec();
-            let mut last_index = batch_size;
-
-            for _j in 0..3 {
-                if intervals_remain > 0 {
-                    if intervals_remain - interval_size > 0 {
-                        last_index += interval_size;
-                        intervals_remain -= interval_size;
-                    } else {
-                        last_index += intervals_remain;
-                        intervals_remain = 0;
-                    }
 
-                    if i % 2 == 0 {
-                        byte_vec = [byte_vec, (current_slice[last_index..(last_index + batch_size)]).to_vec()].concat();
-                    } else {
-                        byte_vec = [(current_slice[last_index..(last_index + batch_size)]).to_vec(), byte_vec].concat();
-                    }
-                } else {
-                    if i % 2 != 0 {
-                        byte_vec = [byte_vec, (current_slice[(last_index + 1)..(last_index + 1 + batch_size)]).to_vec()].concat();
-                    } else {
-                        byte_vec = [(current_slice[(last_index + 1)..(last_index + 1 + batch_size)]).to_vec(), byte_vec].concat();
+            let winning_ticket = calculate_random_number(&tier_entropy, lottery_pubkey, pool_tickets);
+            msg!("Tier {} winning ticket is {} of {}", tier, winning_ticket, pool_tickets);
+
+            find_range_owner(&pool, winning_ticket, |bet| bet.tickets)?
+        } else {
+            match selection_mode {
+                SelectionMode::Uniform => {
+                    calculate_random_number(&tier_entropy, lottery_pubkey, pool.len() as u64) as usize
+                }
+                SelectionMode::Weighted => {
+                    let pool_donations = calculate_pool_weight(&pool, |bet| bet.lamports)?;
+                    if pool_donations == 0 {
+                        return Err(LotteryError::NoDonations.into());
                     }
+
+                    let winning_lamport = calculate_random_number(&tier_entropy, lottery_pubkey, pool_donations);
+                    msg!("Tier {} winning lamport is {} of {}", tier, winning_lamport, pool_donations);
+
+                    find_range_owner(&pool, winning_lamport, |bet| bet.lamports)?
                 }
             }
+        };
+
+        msg!("Tier {} winner index is {}", tier, winner_index);
+        let winner = pool.remove(winner_index);
+        winners.push(winner.pubkey);
+    }
+
+    Ok(winners)
+}
+
+/// sum of tickets or lamports of participants, who haven't won yet
+fn calculate_pool_weight(pool: &[ParticipantBet], weight: impl Fn(&ParticipantBet) -> u64) -> Result<u64, ProgramError> {
+    pool.iter()
+        .try_fold(0u64, |sum, bet| sum.checked_add(weight(bet)))
+        .ok_or_else(|| LotteryError::ArithmeticOverflow.into())
+}
+
+/// participant owns the number if it falls into his [cumulative, cumulative + weight) range
+fn find_range_owner(pool: &[ParticipantBet], number: u64, weight: impl Fn(&ParticipantBet) -> u64) -> Result<usize, ProgramError> {
+    let mut cumulative: u64 = 0;
+    for (index, bet) in pool.iter().enumerate() {
+        cumulative = cumulative.checked_add(weight(bet)).ok_or(LotteryError::ArithmeticOverflow)?;
+        if number < cumulative {
+            return Ok(index);
+        }
+    }
+
+    Err(LotteryError::NoDonations.into())
+}
+
+/// resolving winners of a lottery in Entries mode from the entropy fixed at launch
+/// every tier draws a number of the cumulative index and the caller passes the entry, that covers it,
+/// if that entry was withdrawn or has already won, the number is drawn again and the next passed entry must cover the new one
+pub fn resolve_winner_entries<'a, 'info: 'a, I: Iterator<Item = &'a AccountInfo<'info>>>(
+    program_id: &Pubkey,
+    lottery_account: &LotteryAccount,
+    lottery_pubkey: &Pubkey,
+    entry_accs: &mut I,
+) -> Result<Vec<Pubkey>, ProgramError> {
+    let winners_amount = lottery_account.winners_amount as usize;
+    if (lottery_account.participants_count as usize) < winners_amount {
+        return Err(LotteryError::NotEnoughParticipants.into());
+    }
+
+    if lottery_account.total_weight == 0 {
+        return Err(LotteryError::NoDonations.into());
+    }
+
+    let entropy = Hash::new_from_array(lottery_account.draw_entropy);
+    let mut winners: Vec<Pubkey> = Vec::with_capacity(winners_amount);
+
+    for tier in 0..winners_amount {
+        let mut attempt: u32 = 0;
+        loop {
+            let attempt_entropy = hashv(&[entropy.as_ref(), &[tier as u8], &attempt.to_le_bytes()]);
+            let number = calculate_random_number(&attempt_entropy, lottery_pubkey, lottery_account.total_weight);
+
+            let entry_acc = next_account_info(entry_accs)?;
+            let entry_data = entry_acc.try_borrow_data()?;
+            let entry = load_entry(program_id, lottery_pubkey, entry_acc, &entry_data)?;
+
+            if entry.round != lottery_account.round || number < entry.start || number - entry.start >= entry.weight {
+                return Err(LotteryError::EntryMismatch.into());
+            }
 
-            let byte_array: Result<[u8; 16], _> = byte_vec.try_into();
-            if byte_array.is_ok() {
-                sum += u128::from_le_bytes(byte_array.unwrap()) % (pool_size as u128);
+            if entry.lamports > 0 && !winners.contains(&entry.participant) {
+                msg!("Tier {} winning number is {} of {}, attempt {}", tier, number, lottery_account.total_weight, attempt);
+                winners.push(entry.participant);
+                break;
             }
-        } 
+
+            attempt += 1;
+        }
     }
 
-    (sum % (pool_size as u128)) as usize
-}
\ No newline at end of file
+    Ok(winners)
+}
@@ -0,0 +1,94 @@
+use bytemuck::Zeroable;
+use solana_program::{clock::Clock, pubkey::Pubkey, rent::Rent};
+use solana_program_test::{processor, ProgramTest};
+use solana_sdk::{account::Account, signature::Keypair, signer::Signer};
+
+use lottery::client;
+use lottery::error::LotteryError;
+use lottery::state::{LotteryAccount, LotteryState, StorageMode, LOTTERY_DISCRIMINATOR, LOTTERY_VERSION};
+
+mod common;
+use common::{balance, fund, lottery_account, lottery_args, lottery_error, send, try_send};
+
+const LOTTERY_ID: u64 = 19;
+const BET: u64 = 1_000_000;
+
+#[tokio::test]
+async fn close_waits_for_entries_and_pays_authority_or_treasury_only() {
+    let program_id = Pubkey::new_unique();
+    let program_test = ProgramTest::new("lottery", program_id, processor!(lottery::processor::process_instruction));
+    let mut context = program_test.start_with_context().await;
+
+    let authority = Keypair::new();
+    let participant = Keypair::new();
+    fund(&mut context, &[&authority, &participant], 1_000_000_000).await;
+
+    let clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+    let lottery = client::get_lottery_address(&program_id, &authority.pubkey(), LOTTERY_ID);
+    let args = lottery_args(LOTTERY_ID, clock.unix_timestamp, 10, StorageMode::Entries);
+    send(&mut context, &[client::start_lottery(&program_id, &authority.pubkey(), &lottery, args)], &[&authority]).await;
+    send(&mut context, &[
+        client::donate(&program_id, &participant.pubkey(), &lottery, BET, None, StorageMode::Entries),
+    ], &[&participant]).await;
+
+    send(&mut context, &[
+        client::cancel_lottery(&program_id, &authority.pubkey(), &lottery),
+        client::refund_participants(&program_id, &lottery, &[participant.pubkey()], None, StorageMode::Entries),
+    ], &[&authority]).await;
+
+    // bets are refunded, but the entry still holds its rent
+    let open_entry = try_send(&mut context, &[
+        client::close_lottery(&program_id, &authority.pubkey(), &lottery, &authority.pubkey(), None),
+    ], &[&authority]).await;
+    assert_eq!(open_entry.unwrap_err(), lottery_error(0, LotteryError::EntriesOpen));
+
+    send(&mut context, &[client::close_entry(&program_id, &lottery, &participant.pubkey())], &[]).await;
+
+    let stranger = Pubkey::new_unique();
+    let wrong_destination = try_send(&mut context, &[
+        client::close_lottery(&program_id, &authority.pubkey(), &lottery, &stranger, None),
+    ], &[&authority]).await;
+    assert_eq!(wrong_destination.unwrap_err(), lottery_error(0, LotteryError::InvalidDestination));
+
+    let treasury = lottery_account(&mut context, &lottery).await.treasury;
+    let lottery_lamports = balance(&mut context, &lottery).await;
+    send(&mut context, &[client::close_lottery(&program_id, &authority.pubkey(), &lottery, &treasury, None)], &[&authority]).await;
+
+    assert!(context.banks_client.get_account(lottery).await.unwrap().is_none());
+    assert_eq!(balance(&mut context, &treasury).await, lottery_lamports);
+}
+
+#[tokio::test]
+async fn close_rejects_lottery_with_funds_left() {
+    let program_id = Pubkey::new_unique();
+    let authority = Keypair::new();
+    let lottery = client::get_lottery_address(&program_id, &authority.pubkey(), LOTTERY_ID);
+
+    // cancelled lottery without participants, that still counts lamports as owed
+    let mut header = LotteryAccount::zeroed();
+    header.discriminator = LOTTERY_DISCRIMINATOR;
+    header.version = LOTTERY_VERSION;
+    header.authority = authority.pubkey();
+    header.treasury = Pubkey::new_unique();
+    header.lottery_id = LOTTERY_ID;
+    header.lottery_state = LotteryState::CANCELLED as u8;
+    header.storage_mode = StorageMode::Slots as u8;
+    header.total_lamports = BET;
+    let data = bytemuck::bytes_of(&header).to_vec();
+
+    let mut program_test = ProgramTest::new("lottery", program_id, processor!(lottery::processor::process_instruction));
+    program_test.add_account(lottery, Account {
+        lamports: Rent::default().minimum_balance(data.len()) + BET,
+        data,
+        owner: program_id,
+        executable: false,
+        rent_epoch: 0,
+    });
+    let mut context = program_test.start_with_context().await;
+    fund(&mut context, &[&authority], 1_000_000_000).await;
+
+    let funds_left = try_send(&mut context, &[
+        client::close_lottery(&program_id, &authority.pubkey(), &lottery, &authority.pubkey(), None),
+    ], &[&authority]).await;
+    assert_eq!(funds_left.unwrap_err(), lottery_error(0, LotteryError::FundsPending));
+}
@@ -0,0 +1,97 @@
+#![allow(dead_code)]
+
+use solana_program::{clock::Clock, ed25519_program, instruction::{Instruction, InstructionError}, pubkey::Pubkey, system_instruction};
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{signature::Keypair, signer::Signer, transaction::{Transaction, TransactionError}};
+
+use lottery::client;
+use lottery::error::LotteryError;
+use lottery::instructions::StartLotteryArgs;
+use lottery::state::{LotteryAccount, SelectionMode, StorageMode};
+
+pub async fn try_send(context: &mut ProgramTestContext, instructions: &[Instruction], signers: &[&Keypair]) -> Result<(), TransactionError> {
+    let blockhash = context.get_new_latest_blockhash().await.unwrap();
+    let mut all_signers = vec![&context.payer];
+    all_signers.extend_from_slice(signers);
+
+    let transaction = Transaction::new_signed_with_payer(instructions, Some(&context.payer.pubkey()), &all_signers, blockhash);
+    context.banks_client.process_transaction(transaction).await.map_err(|error| error.unwrap())
+}
+
+pub async fn send(context: &mut ProgramTestContext, instructions: &[Instruction], signers: &[&Keypair]) {
+    try_send(context, instructions, signers).await.unwrap();
+}
+
+pub async fn lottery_account(context: &mut ProgramTestContext, lottery_pubkey: &Pubkey) -> LotteryAccount {
+    let account = context.banks_client.get_account(*lottery_pubkey).await.unwrap().unwrap();
+    client::decode_lottery(&account.data).unwrap().0
+}
+
+pub fn lottery_error(index: u8, error: LotteryError) -> TransactionError {
+    TransactionError::InstructionError(index, InstructionError::Custom(error as u32))
+}
+
+/// lamport lottery with a single winner, slot hashes draw and no fee, that ends in 100 seconds
+pub fn lottery_args(lottery_id: u64, unix_timestamp: i64, max_participants: u32, storage_mode: StorageMode) -> StartLotteryArgs {
+    StartLotteryArgs {
+        lottery_id,
+        max_participants,
+        unix_timestamp: unix_timestamp as u64,
+        lottery_end: unix_timestamp as u64 + 100,
+        commitment: None,
+        reveal_deadline: 0,
+        oracle: None,
+        selection_mode: SelectionMode::Uniform,
+        prize_tiers: vec![100],
+        fee_bps: 0,
+        treasury: Pubkey::new_unique(),
+        ticket_price: 0,
+        min_bet: 0,
+        max_bet: 0,
+        mint: None,
+        storage_mode,
+    }
+}
+
+pub async fn fund(context: &mut ProgramTestContext, accounts: &[&Keypair], lamports: u64) {
+    let payer = context.payer.pubkey();
+    let transfers: Vec<Instruction> = accounts.iter()
+        .map(|account| system_instruction::transfer(&payer, &account.pubkey(), lamports))
+        .collect();
+    send(context, &transfers, &[]).await;
+}
+
+pub async fn set_time(context: &mut ProgramTestContext, unix_timestamp: i64) {
+    let mut clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = unix_timestamp;
+    context.set_sysvar(&clock);
+}
+
+pub async fn balance(context: &mut ProgramTestContext, pubkey: &Pubkey) -> u64 {
+    context.banks_client.get_balance(*pubkey).await.unwrap()
+}
+
+/// ed25519 verification with signature, pubkey and message inlined, the layout the lottery expects
+pub fn oracle_instruction(oracle: &Keypair, message: &[u8]) -> Instruction {
+    const DATA_START: u16 = 16;
+    let pubkey_offset = DATA_START;
+    let signature_offset = pubkey_offset + 32;
+    let message_offset = signature_offset + 64;
+
+    let mut data = vec![1, 0];
+    for offset in [signature_offset, u16::MAX, pubkey_offset, u16::MAX, message_offset, message.len() as u16, u16::MAX] {
+        data.extend(offset.to_le_bytes());
+    }
+    data.extend(oracle.pubkey().to_bytes());
+    data.extend(oracle.sign_message(message).as_ref());
+    data.extend(message);
+
+    Instruction::new_with_bytes(ed25519_program::id(), &data, vec![])
+}
+
+pub fn oracle_message(lottery: &Pubkey, round: u64, draw_slot: u64) -> Vec<u8> {
+    let mut message = lottery.to_bytes().to_vec();
+    message.extend(round.to_le_bytes());
+    message.extend(draw_slot.to_le_bytes());
+    message
+}
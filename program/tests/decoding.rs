@@ -0,0 +1,83 @@
+use bytemuck::Zeroable;
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+use lottery::client;
+use lottery::error::LotteryError;
+use lottery::state::{LotteryAccount, ParticipantBet, StorageMode, LOTTERY_DISCRIMINATOR, LOTTERY_VERSION};
+use lottery::utils::load_lottery_mut;
+
+/// current layout header of a lamport lottery in Slots mode
+fn header(max_participants: u32, participants_count: u32) -> LotteryAccount {
+    LotteryAccount {
+        discriminator: LOTTERY_DISCRIMINATOR,
+        version: LOTTERY_VERSION,
+        max_participants,
+        participants_count,
+        storage_mode: StorageMode::Slots as u8,
+        ..LotteryAccount::zeroed()
+    }
+}
+
+fn lottery_data(header: &LotteryAccount, slots: &[ParticipantBet]) -> Vec<u8> {
+    let mut data = bytemuck::bytes_of(header).to_vec();
+    data.extend(bytemuck::cast_slice::<ParticipantBet, u8>(slots));
+    data
+}
+
+fn bet(lamports: u64) -> ParticipantBet {
+    ParticipantBet { pubkey: Pubkey::new_unique(), lamports, ..ParticipantBet::default() }
+}
+
+/// error of decoding over RPC and of loading for writing, they have to agree
+fn decoding_errors(data: &[u8]) -> (ProgramError, ProgramError) {
+    let decode_error = client::decode_lottery(data).unwrap_err();
+
+    // load_lottery_mut casts in place, so the bytes have to be aligned as on chain
+    let mut aligned = vec![0u64; data.len().div_ceil(8)];
+    let aligned_bytes: &mut [u8] = bytemuck::cast_slice_mut(&mut aligned);
+    aligned_bytes[..data.len()].copy_from_slice(data);
+    let load_error = load_lottery_mut(&mut aligned_bytes[..data.len()]).unwrap_err();
+
+    (decode_error, load_error)
+}
+
+#[test]
+fn truncated_header_is_rejected() {
+    let data = lottery_data(&header(2, 0), &[]);
+
+    for len in [LOTTERY_DISCRIMINATOR.len(), LOTTERY_DISCRIMINATOR.len() + 1, data.len() - 1] {
+        let error = client::decode_lottery(&data[..len]).unwrap_err();
+        assert_eq!(error, LotteryError::InvalidLotteryAccount.into(), "header truncated to {len} bytes");
+    }
+}
+
+#[test]
+fn participants_count_over_max_participants_is_rejected() {
+    // slots are there, but the lottery can't have that many participants
+    let data = lottery_data(&header(1, 2), &[bet(1), bet(2)]);
+
+    let (decode_error, load_error) = decoding_errors(&data);
+    assert_eq!(decode_error, LotteryError::InvalidParticipantsCount.into());
+    assert_eq!(load_error, LotteryError::InvalidParticipantsCount.into());
+}
+
+#[test]
+fn participants_count_over_slot_capacity_is_rejected() {
+    // count fits max participants, but the account wasn't grown for it
+    let data = lottery_data(&header(10, 3), &[bet(1), bet(2)]);
+
+    let (decode_error, load_error) = decoding_errors(&data);
+    assert_eq!(decode_error, LotteryError::InvalidParticipantsCount.into());
+    assert_eq!(load_error, LotteryError::InvalidParticipantsCount.into());
+}
+
+#[test]
+fn stale_slots_after_participants_count_are_ignored() {
+    // slots left by withdrawn or refunded participants aren't zeroed
+    let used = bet(1_000);
+    let data = lottery_data(&header(3, 1), &[used, bet(2_000), bet(3_000)]);
+
+    let (decoded, participants) = client::decode_lottery(&data).unwrap();
+    assert_eq!(decoded.participants_count, 1);
+    assert_eq!(participants, vec![used]);
+}
@@ -0,0 +1,264 @@
+use solana_program::{clock::Clock, hash::{hashv, Hash}, pubkey::Pubkey, system_instruction};
+use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+use solana_sdk::{signature::Keypair, signer::Signer, transaction::TransactionError};
+
+use lottery::client;
+use lottery::error::LotteryError;
+use lottery::instructions::StartLotteryArgs;
+use lottery::state::{LotteryEntry, LotteryState, RandomnessMode, SelectionMode, StorageMode};
+use lottery::utils::calculate_random_number;
+
+mod common;
+use common::{balance, fund, lottery_account, lottery_args, lottery_error, oracle_instruction, oracle_message, send, set_time, try_send};
+
+const LOTTERY_ID: u64 = 13;
+const BET: u64 = 1_000_000;
+
+async fn program_context(program_id: &Pubkey) -> ProgramTestContext {
+    let program_test = ProgramTest::new("lottery", *program_id, processor!(lottery::processor::process_instruction));
+    program_test.start_with_context().await
+}
+
+struct TestLottery {
+    context: ProgramTestContext,
+    program_id: Pubkey,
+    authority: Keypair,
+    oracle: Keypair,
+    lottery: Pubkey,
+    participants: Vec<Keypair>,
+    lottery_end: i64,
+}
+
+/// weighted lamport lottery in Entries mode, drawn by a local oracle, so tests can pick the winning numbers
+async fn start_lottery(participants_amount: usize) -> TestLottery {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_context(&program_id).await;
+
+    let authority = Keypair::new();
+    let oracle = Keypair::new();
+    let participants: Vec<Keypair> = (0..participants_amount).map(|_| Keypair::new()).collect();
+    fund(&mut context, &[&authority], 1_000_000_000).await;
+    fund(&mut context, &participants.iter().collect::<Vec<_>>(), 100_000_000).await;
+
+    let clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+    let lottery = client::get_lottery_address(&program_id, &authority.pubkey(), LOTTERY_ID);
+    let args = StartLotteryArgs {
+        oracle: Some(oracle.pubkey()),
+        selection_mode: SelectionMode::Weighted,
+        ..lottery_args(LOTTERY_ID, clock.unix_timestamp, 10, StorageMode::Entries)
+    };
+    let lottery_end = args.lottery_end as i64;
+    send(&mut context, &[client::start_lottery(&program_id, &authority.pubkey(), &lottery, args)], &[&authority]).await;
+
+    TestLottery { context, program_id, authority, oracle, lottery, participants, lottery_end }
+}
+
+async fn bet(test_lottery: &mut TestLottery, participant: usize, amount: u64) -> Result<(), TransactionError> {
+    let TestLottery { context, program_id, lottery, participants, .. } = test_lottery;
+    let participant = &participants[participant];
+
+    try_send(context, &[
+        client::donate(program_id, &participant.pubkey(), lottery, amount, None, StorageMode::Entries),
+    ], &[participant]).await
+}
+
+async fn withdraw(test_lottery: &mut TestLottery, participant: usize) {
+    let TestLottery { context, program_id, lottery, participants, .. } = test_lottery;
+    let participant = &participants[participant];
+
+    send(context, &[
+        client::withdraw_bet(program_id, &participant.pubkey(), lottery, None, StorageMode::Entries),
+    ], &[participant]).await;
+}
+
+async fn close_entry(test_lottery: &mut TestLottery, participant: usize) -> Result<(), TransactionError> {
+    let participant = test_lottery.participants[participant].pubkey();
+    try_send(&mut test_lottery.context, &[
+        client::close_entry(&test_lottery.program_id, &test_lottery.lottery, &participant),
+    ], &[]).await
+}
+
+async fn entry(test_lottery: &mut TestLottery, participant: usize) -> LotteryEntry {
+    let entry = client::get_entry_address(&test_lottery.program_id, &test_lottery.lottery, &test_lottery.participants[participant].pubkey());
+    let account = test_lottery.context.banks_client.get_account(entry).await.unwrap().unwrap();
+    client::decode_entry(&account.data).unwrap()
+}
+
+/// number of the cumulative index, drawn for the tier on the given attempt, the same way ResolveWinners draws it
+fn drawn_number(draw_entropy: [u8; 32], lottery: &Pubkey, total_weight: u64, tier: u8, attempt: u32) -> u64 {
+    let entropy = Hash::new_from_array(draw_entropy);
+    let attempt_entropy = hashv(&[entropy.as_ref(), &[tier], &attempt.to_le_bytes()]);
+    calculate_random_number(&attempt_entropy, lottery, total_weight)
+}
+
+#[tokio::test]
+async fn pre_funded_lottery_and_entry_addresses_are_still_created() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_context(&program_id).await;
+
+    let authority = Keypair::new();
+    let participant = Keypair::new();
+    fund(&mut context, &[&authority, &participant], 1_000_000_000).await;
+
+    let lottery = client::get_lottery_address(&program_id, &authority.pubkey(), LOTTERY_ID);
+    let entry = client::get_entry_address(&program_id, &lottery, &participant.pubkey());
+
+    // addresses are known in advance, anyone can send lamports there before the accounts are created
+    let payer = context.payer.pubkey();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    send(&mut context, &[
+        system_instruction::transfer(&payer, &lottery, rent.minimum_balance(0)),
+        system_instruction::transfer(&payer, &entry, rent.minimum_balance(0)),
+    ], &[]).await;
+
+    let clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+    let args = lottery_args(LOTTERY_ID, clock.unix_timestamp, 10, StorageMode::Entries);
+    send(&mut context, &[client::start_lottery(&program_id, &authority.pubkey(), &lottery, args)], &[&authority]).await;
+    send(&mut context, &[
+        client::donate(&program_id, &participant.pubkey(), &lottery, 1_000_000, None, StorageMode::Entries),
+    ], &[&participant]).await;
+
+    let started = lottery_account(&mut context, &lottery).await;
+    assert_eq!(started.lottery_state().unwrap(), LotteryState::IN_PROGRESS);
+    assert_eq!(started.open_entries, 1);
+
+    let entry_account = context.banks_client.get_account(entry).await.unwrap().unwrap();
+    assert_eq!(entry_account.owner, program_id);
+    assert_eq!(entry_account.data.len(), size_of::<LotteryEntry>());
+    assert_eq!(client::decode_entry(&entry_account.data).unwrap().lamports, 1_000_000);
+}
+
+#[tokio::test]
+async fn withdrawn_middle_range_is_redrawn() {
+    let mut test_lottery = start_lottery(3).await;
+    for participant in 0..3 {
+        bet(&mut test_lottery, participant, BET).await.unwrap();
+    }
+
+    // ranges follow the order of the first bets
+    for participant in 0..3 {
+        let entry = entry(&mut test_lottery, participant).await;
+        assert_eq!((entry.start, entry.weight), (participant as u64 * BET, BET));
+    }
+
+    // the last range can grow, the others would overlap with the next ones
+    bet(&mut test_lottery, 2, BET).await.unwrap();
+    let top_up = bet(&mut test_lottery, 0, BET).await;
+    assert_eq!(top_up.unwrap_err(), lottery_error(0, LotteryError::EntryRangeClosed));
+
+    // middle range stays in the index, so the ranges after it don't move
+    withdraw(&mut test_lottery, 1).await;
+    let withdrawn = entry(&mut test_lottery, 1).await;
+    assert_eq!((withdrawn.lamports, withdrawn.start, withdrawn.weight), (0, BET, BET));
+    let rebet = bet(&mut test_lottery, 1, BET).await;
+    assert_eq!(rebet.unwrap_err(), lottery_error(0, LotteryError::EntryRangeClosed));
+
+    let open = lottery_account(&mut test_lottery.context, &test_lottery.lottery).await;
+    assert_eq!((open.total_weight, open.participants_count, open.total_lamports), (4 * BET, 2, 3 * BET));
+
+    // oracle signature is picked, so the first number lands on the withdrawn range
+    test_lottery.context.warp_to_slot(200).unwrap();
+    set_time(&mut test_lottery.context, test_lottery.lottery_end).await;
+    let slot = test_lottery.context.banks_client.get_root_slot().await.unwrap();
+    let lottery = test_lottery.lottery;
+    let mut draw_slot = slot - 100;
+    loop {
+        let signature = test_lottery.oracle.sign_message(&oracle_message(&lottery, open.round, draw_slot));
+        let number = drawn_number(hashv(&[signature.as_ref()]).to_bytes(), &lottery, open.total_weight, 0, 0);
+        if (BET..2 * BET).contains(&number) {
+            break;
+        }
+        draw_slot += 1;
+    }
+    assert!(draw_slot <= slot);
+
+    let TestLottery { context, program_id, authority, oracle, .. } = &mut test_lottery;
+    send(context, &[
+        oracle_instruction(oracle, &oracle_message(&lottery, open.round, draw_slot)),
+        client::launch_lottery(program_id, &authority.pubkey(), &lottery, RandomnessMode::Oracle, None),
+    ], &[authority]).await;
+
+    let launched = lottery_account(&mut test_lottery.context, &lottery).await;
+    let entries: Vec<Pubkey> = test_lottery.participants.iter()
+        .map(|participant| client::get_entry_address(&test_lottery.program_id, &lottery, &participant.pubkey()))
+        .collect();
+    let covering = |number: u64| if number < BET { 0 } else if number < 2 * BET { 1 } else { 2 };
+
+    // entry, that doesn't cover the drawn number, is rejected
+    let skipped = try_send(&mut test_lottery.context, &[
+        client::resolve_winners(&test_lottery.program_id, &lottery, &entries[..1]),
+    ], &[]).await;
+    assert_eq!(skipped.unwrap_err(), lottery_error(0, LotteryError::EntryMismatch));
+
+    // withdrawn entry covers the first number, it's passed and the number is drawn again until a live entry is hit
+    let mut passed = Vec::new();
+    let mut attempt = 0;
+    let winner = loop {
+        let participant = covering(drawn_number(launched.draw_entropy, &lottery, launched.total_weight, 0, attempt));
+        passed.push(entries[participant]);
+        if participant != 1 {
+            break participant;
+        }
+        attempt += 1;
+    };
+    assert!(passed.len() > 1);
+    assert_eq!(passed[0], entries[1]);
+
+    send(&mut test_lottery.context, &[client::resolve_winners(&test_lottery.program_id, &lottery, &passed)], &[]).await;
+    let resolved = lottery_account(&mut test_lottery.context, &lottery).await;
+    assert_eq!(resolved.winners[0], test_lottery.participants[winner].pubkey());
+
+    // entries hold bets of the current round until it's paid out
+    let in_use = close_entry(&mut test_lottery, 1).await;
+    assert_eq!(in_use.unwrap_err(), lottery_error(0, LotteryError::EntryInUse));
+
+    let TestLottery { context, program_id, authority, participants, .. } = &mut test_lottery;
+    send(context, &[
+        client::complete_lottery(program_id, &authority.pubkey(), &lottery, &resolved.treasury, &[participants[winner].pubkey()], false),
+    ], &[authority]).await;
+
+    for participant in 0..3 {
+        let participant_key = test_lottery.participants[participant].pubkey();
+        let before = balance(&mut test_lottery.context, &participant_key).await;
+        close_entry(&mut test_lottery, participant).await.unwrap();
+        assert!(balance(&mut test_lottery.context, &participant_key).await > before);
+    }
+    assert_eq!(lottery_account(&mut test_lottery.context, &lottery).await.open_entries, 0);
+}
+
+#[tokio::test]
+async fn withdrawn_tail_range_is_given_back() {
+    let mut test_lottery = start_lottery(2).await;
+    bet(&mut test_lottery, 0, BET).await.unwrap();
+    bet(&mut test_lottery, 1, 2 * BET).await.unwrap();
+
+    // the last range is cut off the index
+    withdraw(&mut test_lottery, 1).await;
+    let withdrawn = entry(&mut test_lottery, 1).await;
+    assert_eq!((withdrawn.lamports, withdrawn.weight), (0, 0));
+    let cut = lottery_account(&mut test_lottery.context, &test_lottery.lottery).await;
+    assert_eq!((cut.total_weight, cut.participants_count, cut.total_lamports), (BET, 1, BET));
+
+    // so the first range is the last one again and can grow
+    bet(&mut test_lottery, 0, BET).await.unwrap();
+
+    // the next bet of the withdrawn entry opens a new range at the end of the index
+    bet(&mut test_lottery, 1, 3 * BET).await.unwrap();
+    let rebet = entry(&mut test_lottery, 1).await;
+    assert_eq!((rebet.lamports, rebet.start, rebet.weight), (3 * BET, 2 * BET, 3 * BET));
+
+    let reopened = lottery_account(&mut test_lottery.context, &test_lottery.lottery).await;
+    assert_eq!((reopened.total_weight, reopened.participants_count, reopened.total_lamports), (5 * BET, 2, 5 * BET));
+    assert_eq!(reopened.open_entries, 2);
+
+    // entries of a cancelled lottery are settled once their bets are refunded
+    let TestLottery { context, program_id, authority, lottery, participants, .. } = &mut test_lottery;
+    send(context, &[client::cancel_lottery(program_id, &authority.pubkey(), lottery)], &[authority]).await;
+    let participant = participants[1].pubkey();
+    send(context, &[client::refund_participants(program_id, lottery, &[participant], None, StorageMode::Entries)], &[]).await;
+
+    let unrefunded = close_entry(&mut test_lottery, 0).await;
+    assert_eq!(unrefunded.unwrap_err(), lottery_error(0, LotteryError::EntryInUse));
+    close_entry(&mut test_lottery, 1).await.unwrap();
+    assert_eq!(lottery_account(&mut test_lottery.context, &test_lottery.lottery).await.open_entries, 1);
+}
@@ -0,0 +1,118 @@
+use solana_program::{clock::Clock, pubkey::Pubkey};
+use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+use solana_sdk::{signature::Keypair, signer::Signer};
+
+use lottery::client;
+use lottery::error::LotteryError;
+use lottery::state::{LotteryAccount, LotteryState, ParticipantBet, StorageMode};
+
+mod common;
+use common::{fund, lottery_account, lottery_args, lottery_error, send, try_send};
+
+const LOTTERY_ID: u64 = 17;
+const BET: u64 = 1_000_000;
+/// more slots than fit into MAX_PERMITTED_DATA_INCREASE
+const TOO_MANY_SLOTS: u32 = 200;
+
+struct TestLottery {
+    context: ProgramTestContext,
+    program_id: Pubkey,
+    authority: Keypair,
+    lottery: Pubkey,
+}
+
+async fn start_lottery(max_participants: u32, storage_mode: StorageMode) -> TestLottery {
+    let program_id = Pubkey::new_unique();
+    let program_test = ProgramTest::new("lottery", program_id, processor!(lottery::processor::process_instruction));
+    let mut context = program_test.start_with_context().await;
+
+    let authority = Keypair::new();
+    fund(&mut context, &[&authority], 1_000_000_000).await;
+
+    let clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+    let lottery = client::get_lottery_address(&program_id, &authority.pubkey(), LOTTERY_ID);
+    let args = lottery_args(LOTTERY_ID, clock.unix_timestamp, max_participants, storage_mode);
+    send(&mut context, &[client::start_lottery(&program_id, &authority.pubkey(), &lottery, args)], &[&authority]).await;
+
+    TestLottery { context, program_id, authority, lottery }
+}
+
+async fn account_size(test_lottery: &mut TestLottery) -> usize {
+    test_lottery.context.banks_client.get_account(test_lottery.lottery).await.unwrap().unwrap().data.len()
+}
+
+fn lottery_size(max_participants: u32) -> usize {
+    size_of::<LotteryAccount>() + max_participants as usize * size_of::<ParticipantBet>()
+}
+
+#[tokio::test]
+async fn expand_grows_slots_and_reopens_full_lottery() {
+    let mut test_lottery = start_lottery(1, StorageMode::Slots).await;
+    let participants = [Keypair::new(), Keypair::new()];
+    fund(&mut test_lottery.context, &[&participants[0], &participants[1]], 100_000_000).await;
+
+    let TestLottery { context, program_id, authority, lottery } = &mut test_lottery;
+    send(context, &[client::donate(program_id, &participants[0].pubkey(), lottery, BET, None, StorageMode::Slots)], &[&participants[0]]).await;
+    assert_eq!(lottery_account(context, lottery).await.lottery_state().unwrap(), LotteryState::BETS_CLOSED);
+
+    send(context, &[client::expand_lottery(program_id, &authority.pubkey(), lottery, 3)], &[authority]).await;
+    let expanded = lottery_account(context, lottery).await;
+    assert_eq!(expanded.max_participants, 3);
+    assert_eq!(expanded.lottery_state().unwrap(), LotteryState::IN_PROGRESS);
+    assert_eq!(account_size(&mut test_lottery).await, lottery_size(3));
+
+    // bet placed before the expansion is kept, new slots take the next bets
+    let TestLottery { context, program_id, lottery, .. } = &mut test_lottery;
+    send(context, &[client::donate(program_id, &participants[1].pubkey(), lottery, BET, None, StorageMode::Slots)], &[&participants[1]]).await;
+    let account = context.banks_client.get_account(*lottery).await.unwrap().unwrap();
+    let (header, slots) = client::decode_lottery(&account.data).unwrap();
+    assert_eq!(header.participants_count, 2);
+    assert!(slots.iter().any(|slot| slot.pubkey == participants[0].pubkey() && slot.lamports == BET));
+}
+
+#[tokio::test]
+async fn expand_rejects_shrinking_entries_mode_and_too_many_slots() {
+    let mut test_lottery = start_lottery(5, StorageMode::Slots).await;
+    let TestLottery { context, program_id, authority, lottery } = &mut test_lottery;
+
+    let shrink = try_send(context, &[client::expand_lottery(program_id, &authority.pubkey(), lottery, 4)], &[authority]).await;
+    assert_eq!(shrink.unwrap_err(), lottery_error(0, LotteryError::MaxParticipantsDecreased));
+
+    let too_many = try_send(context, &[client::expand_lottery(program_id, &authority.pubkey(), lottery, 5 + TOO_MANY_SLOTS)], &[authority]).await;
+    assert_eq!(too_many.unwrap_err(), lottery_error(0, LotteryError::GrowthLimitExceeded));
+
+    // the limit is per instruction, so the same size is reached in two steps
+    send(context, &[
+        client::expand_lottery(program_id, &authority.pubkey(), lottery, 5 + TOO_MANY_SLOTS / 2),
+        client::expand_lottery(program_id, &authority.pubkey(), lottery, 5 + TOO_MANY_SLOTS),
+    ], &[authority]).await;
+    assert_eq!(account_size(&mut test_lottery).await, lottery_size(5 + TOO_MANY_SLOTS));
+
+    let mut entries_lottery = start_lottery(5, StorageMode::Entries).await;
+    let TestLottery { context, program_id, authority, lottery } = &mut entries_lottery;
+    let entries = try_send(context, &[client::expand_lottery(program_id, &authority.pubkey(), lottery, 10)], &[authority]).await;
+    assert_eq!(entries.unwrap_err(), lottery_error(0, LotteryError::NoParticipantSlots));
+}
+
+#[tokio::test]
+async fn restart_with_bigger_max_grows_account() {
+    let mut test_lottery = start_lottery(2, StorageMode::Slots).await;
+    assert_eq!(account_size(&mut test_lottery).await, lottery_size(2));
+
+    let TestLottery { context, program_id, authority, lottery } = &mut test_lottery;
+    send(context, &[client::cancel_lottery(program_id, &authority.pubkey(), lottery)], &[authority]).await;
+
+    // start grows the account by the same per instruction limit as ExpandLottery
+    let clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+    let args = lottery_args(LOTTERY_ID, clock.unix_timestamp, 2 + TOO_MANY_SLOTS, StorageMode::Slots);
+    let too_many = try_send(context, &[client::start_lottery(program_id, &authority.pubkey(), lottery, args)], &[authority]).await;
+    assert_eq!(too_many.unwrap_err(), lottery_error(0, LotteryError::GrowthLimitExceeded));
+
+    let args = lottery_args(LOTTERY_ID, clock.unix_timestamp, 10, StorageMode::Slots);
+    send(context, &[client::start_lottery(program_id, &authority.pubkey(), lottery, args)], &[authority]).await;
+
+    let restarted = lottery_account(context, lottery).await;
+    assert_eq!(restarted.max_participants, 10);
+    assert_eq!(restarted.lottery_state().unwrap(), LotteryState::IN_PROGRESS);
+    assert_eq!(account_size(&mut test_lottery).await, lottery_size(10));
+}
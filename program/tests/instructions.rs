@@ -0,0 +1,65 @@
+use solana_program::pubkey::Pubkey;
+
+use lottery::instructions::{LotteryInstructions, StartLotteryArgs};
+use lottery::state::{SelectionMode, StorageMode};
+
+fn start_args() -> StartLotteryArgs {
+    StartLotteryArgs {
+        lottery_id: 7,
+        max_participants: 10,
+        unix_timestamp: 1_700_000_000,
+        lottery_end: 1_700_000_100,
+        commitment: Some([3; 32]),
+        reveal_deadline: 1_700_000_200,
+        oracle: None,
+        selection_mode: SelectionMode::Weighted,
+        prize_tiers: vec![70, 30],
+        fee_bps: 500,
+        treasury: Pubkey::new_unique(),
+        ticket_price: 0,
+        min_bet: 1_000,
+        max_bet: 1_000_000,
+        mint: Some(Pubkey::new_unique()),
+        storage_mode: StorageMode::Entries,
+    }
+}
+
+#[test]
+fn every_instruction_survives_pack_and_unpack() {
+    let instructions = vec![
+        LotteryInstructions::StartLottery(start_args()),
+        LotteryInstructions::DonateInstruction(42),
+        LotteryInstructions::LaunchLottery(None),
+        LotteryInstructions::LaunchLottery(Some([9; 32])),
+        LotteryInstructions::CompleteLottery,
+        LotteryInstructions::SetTreasury(Pubkey::new_unique()),
+        LotteryInstructions::ProposeAuthority(Pubkey::new_unique()),
+        LotteryInstructions::AcceptAuthority,
+        LotteryInstructions::CancelAuthorityProposal,
+        LotteryInstructions::CancelLottery,
+        LotteryInstructions::RefundParticipants,
+        LotteryInstructions::WithdrawBet,
+        LotteryInstructions::ResolveWinners,
+        LotteryInstructions::ExpandLottery(500),
+        LotteryInstructions::CloseLottery,
+        LotteryInstructions::MigrateLottery,
+        LotteryInstructions::CloseEntry,
+    ];
+
+    for instruction in instructions {
+        let data = instruction.pack();
+        let unpacked = LotteryInstructions::unpack(&data).unwrap();
+
+        assert_eq!(format!("{:?}", unpacked), format!("{:?}", instruction));
+        assert_eq!(unpacked.pack(), data);
+    }
+}
+
+#[test]
+fn malformed_instruction_data_is_rejected() {
+    assert!(LotteryInstructions::unpack(&[]).is_err());
+    assert!(LotteryInstructions::unpack(&[1, 0, 0]).is_err());
+    assert!(LotteryInstructions::unpack(&[2, 0]).is_err());
+    assert!(LotteryInstructions::unpack(&[12, 1]).is_err());
+    assert!(LotteryInstructions::unpack(&[255]).is_err());
+}
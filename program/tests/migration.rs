@@ -0,0 +1,212 @@
+use bytemuck::Zeroable;
+use solana_program::{
+    instruction::InstructionError,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction,
+};
+use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+use solana_sdk::{account::Account, signature::Keypair, signer::Signer, transaction::TransactionError};
+
+use lottery::client;
+use lottery::error::LotteryError;
+use lottery::state::{
+    LotteryAccountV1, LotteryEntry, LotteryState, ParticipantBet, RandomnessMode, SelectionMode, StorageMode,
+    ENTRY_SEED, LOTTERY_DISCRIMINATOR, LOTTERY_V1_HEADER_SIZE, LOTTERY_VERSION, MAX_WINNERS,
+};
+
+mod common;
+use common::{lottery_error, send, try_send};
+
+const LOTTERY_ID: u64 = 5;
+
+/// v1 header with every field set, so a field moved to a wrong place would show up
+fn v1_header(authority: &Pubkey, storage_mode: StorageMode) -> LotteryAccountV1 {
+    let mut winners = [Pubkey::default(); MAX_WINNERS];
+    winners[0] = Pubkey::new_unique();
+    let mut prize_tiers = [0; MAX_WINNERS];
+    prize_tiers[..2].copy_from_slice(&[60, 40]);
+
+    LotteryAccountV1 {
+        winners,
+        authority: *authority,
+        treasury: Pubkey::new_unique(),
+        pending_authority: Pubkey::new_unique(),
+        oracle: Pubkey::new_unique(),
+        mint: Pubkey::default(),
+        commitment: [1; 32],
+        draw_entropy: [2; 32],
+        lottery_start: 1_700_000_000,
+        lottery_end: 1_700_000_100,
+        lottery_id: LOTTERY_ID,
+        reveal_deadline: 1_700_000_200,
+        ticket_price: 0,
+        min_bet: 1_000,
+        max_bet: 9_000_000,
+        total_tickets: 0,
+        round: 41,
+        total_weight: 3_000_000,
+        total_lamports: 3_000_000,
+        max_participants: 2,
+        participants_count: 1,
+        fee_bps: 250,
+        lottery_state: LotteryState::IN_PROGRESS as u8,
+        randomness_mode: RandomnessMode::CommitReveal as u8,
+        selection_mode: SelectionMode::Weighted as u8,
+        winners_amount: 2,
+        prize_tiers,
+        storage_mode: storage_mode as u8,
+        reserved: [0; 7],
+    }
+}
+
+/// v1 account is the frozen v1 header followed by participant slots
+fn v1_lottery_data(header: &LotteryAccountV1, slots: &[ParticipantBet]) -> Vec<u8> {
+    let mut data = bytemuck::bytes_of(header).to_vec();
+    assert_eq!(data.len(), LOTTERY_V1_HEADER_SIZE);
+
+    data.extend(bytemuck::cast_slice::<ParticipantBet, u8>(slots));
+    data
+}
+
+fn program_account(program_id: &Pubkey, data: Vec<u8>, extra_lamports: u64) -> Account {
+    Account {
+        lamports: Rent::default().minimum_balance(data.len()) + extra_lamports,
+        data,
+        owner: *program_id,
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+async fn start(program_test: ProgramTest, authority: &Keypair) -> ProgramTestContext {
+    let mut context = program_test.start_with_context().await;
+    let payer = context.payer.pubkey();
+    send(&mut context, &[system_instruction::transfer(&payer, &authority.pubkey(), 1_000_000_000)], &[]).await;
+    context
+}
+
+#[tokio::test]
+async fn v1_lottery_is_migrated_with_header_and_slots() {
+    let program_id = Pubkey::new_unique();
+    let authority = Keypair::new();
+    let lottery = client::get_lottery_address(&program_id, &authority.pubkey(), LOTTERY_ID);
+
+    let mut header = v1_header(&authority.pubkey(), StorageMode::Slots);
+    // v1 didn't keep the sum of bets in Slots mode
+    header.total_lamports = 0;
+
+    let bet = ParticipantBet { pubkey: Pubkey::new_unique(), lamports: 3_000_000, ..ParticipantBet::default() };
+    let data = v1_lottery_data(&header, &[bet, ParticipantBet::default()]);
+
+    let mut program_test = ProgramTest::new("lottery", program_id, processor!(lottery::processor::process_instruction));
+    program_test.add_account(lottery, program_account(&program_id, data, bet.lamports));
+    let mut context = start(program_test, &authority).await;
+
+    // handlers don't read accounts of the old layout
+    let stale = try_send(&mut context, &[
+        client::set_treasury(&program_id, &authority.pubkey(), &lottery, Pubkey::new_unique()),
+    ], &[&authority]).await;
+    assert_eq!(
+        stale.unwrap_err(),
+        TransactionError::InstructionError(0, InstructionError::Custom(LotteryError::UnsupportedVersion as u32))
+    );
+
+    // only the authority can migrate
+    let stranger = Keypair::new();
+    let payer = context.payer.pubkey();
+    send(&mut context, &[system_instruction::transfer(&payer, &stranger.pubkey(), 1_000_000_000)], &[]).await;
+    let foreign = try_send(&mut context, &[client::migrate_lottery(&program_id, &stranger.pubkey(), &lottery, &[])], &[&stranger]).await;
+    assert_eq!(foreign.unwrap_err(), lottery_error(0, LotteryError::InvalidAuthority));
+
+    send(&mut context, &[client::migrate_lottery(&program_id, &authority.pubkey(), &lottery, &[])], &[&authority]).await;
+
+    let account = context.banks_client.get_account(lottery).await.unwrap().unwrap();
+    assert_eq!(account.data.len(), LOTTERY_V1_HEADER_SIZE + 8 + 2 * size_of::<ParticipantBet>());
+
+    let (migrated, participants) = client::decode_lottery(&account.data).unwrap();
+    assert_eq!(migrated.discriminator, LOTTERY_DISCRIMINATOR);
+    assert_eq!(migrated.version, LOTTERY_VERSION);
+    assert_eq!(migrated.winners, header.winners);
+    assert_eq!(migrated.authority, header.authority);
+    assert_eq!(migrated.treasury, header.treasury);
+    assert_eq!(migrated.pending_authority, header.pending_authority);
+    assert_eq!(migrated.oracle, header.oracle);
+    assert_eq!(migrated.mint, header.mint);
+    assert_eq!(migrated.commitment, header.commitment);
+    assert_eq!(migrated.draw_entropy, header.draw_entropy);
+    assert_eq!(migrated.lottery_start, header.lottery_start);
+    assert_eq!(migrated.lottery_end, header.lottery_end);
+    assert_eq!(migrated.lottery_id, LOTTERY_ID);
+    assert_eq!(migrated.reveal_deadline, header.reveal_deadline);
+    assert_eq!(migrated.ticket_price, header.ticket_price);
+    assert_eq!(migrated.min_bet, header.min_bet);
+    assert_eq!(migrated.max_bet, header.max_bet);
+    assert_eq!(migrated.total_tickets, header.total_tickets);
+    assert_eq!(migrated.round, header.round);
+    assert_eq!(migrated.total_weight, header.total_weight);
+    // recomputed from the slots
+    assert_eq!(migrated.total_lamports, bet.lamports);
+    assert_eq!(migrated.max_participants, 2);
+    assert_eq!(migrated.participants_count, 1);
+    assert_eq!(migrated.fee_bps, 250);
+    assert_eq!(migrated.lottery_state().unwrap(), LotteryState::IN_PROGRESS);
+    assert_eq!(migrated.randomness_mode().unwrap(), RandomnessMode::CommitReveal);
+    assert_eq!(migrated.selection_mode().unwrap(), SelectionMode::Weighted);
+    assert_eq!(migrated.winners_amount, 2);
+    assert_eq!(migrated.prize_tiers, header.prize_tiers);
+    assert_eq!(migrated.storage_mode().unwrap(), StorageMode::Slots);
+    assert_eq!(migrated.reserved, [0; 3]);
+    assert_eq!(migrated.open_entries, 0);
+    assert_eq!(participants, vec![bet]);
+
+    // migrated lottery is handled again, migrating it twice does nothing
+    send(&mut context, &[
+        client::set_treasury(&program_id, &authority.pubkey(), &lottery, header.treasury),
+        client::migrate_lottery(&program_id, &authority.pubkey(), &lottery, &[]),
+    ], &[&authority]).await;
+}
+
+#[tokio::test]
+async fn v1_lottery_in_entries_mode_counts_passed_entries() {
+    let program_id = Pubkey::new_unique();
+    let authority = Keypair::new();
+    let lottery = client::get_lottery_address(&program_id, &authority.pubkey(), LOTTERY_ID);
+    let header = v1_header(&authority.pubkey(), StorageMode::Entries);
+
+    let mut program_test = ProgramTest::new("lottery", program_id, processor!(lottery::processor::process_instruction));
+    program_test.add_account(lottery, program_account(&program_id, v1_lottery_data(&header, &[]), header.total_lamports));
+
+    let participants = [Pubkey::new_unique(), Pubkey::new_unique()];
+    let mut entries = Vec::new();
+    for participant in participants.iter() {
+        let (entry, bump) = Pubkey::find_program_address(&[ENTRY_SEED.as_bytes(), lottery.as_ref(), participant.as_ref()], &program_id);
+        let entry_data = LotteryEntry { lottery, participant: *participant, round: header.round, lamports: 1_500_000, bump, ..LotteryEntry::zeroed() };
+        program_test.add_account(entry, program_account(&program_id, bytemuck::bytes_of(&entry_data).to_vec(), 0));
+        entries.push(entry);
+    }
+    let mut context = start(program_test, &authority).await;
+
+    // every entry is counted once
+    let duplicated = try_send(&mut context, &[
+        client::migrate_lottery(&program_id, &authority.pubkey(), &lottery, &[entries[0], entries[0]]),
+    ], &[&authority]).await;
+    assert_eq!(duplicated.unwrap_err(), lottery_error(0, LotteryError::InvalidEntry));
+
+    send(&mut context, &[client::migrate_lottery(&program_id, &authority.pubkey(), &lottery, &entries)], &[&authority]).await;
+
+    let account = context.banks_client.get_account(lottery).await.unwrap().unwrap();
+    assert_eq!(account.data.len(), LOTTERY_V1_HEADER_SIZE + 8);
+
+    let (migrated, participants) = client::decode_lottery(&account.data).unwrap();
+    assert_eq!(migrated.version, LOTTERY_VERSION);
+    assert_eq!(migrated.authority, header.authority);
+    assert_eq!(migrated.round, header.round);
+    assert_eq!(migrated.total_weight, header.total_weight);
+    // Entries mode kept the sum of bets already
+    assert_eq!(migrated.total_lamports, header.total_lamports);
+    assert_eq!(migrated.participants_count, header.participants_count);
+    assert_eq!(migrated.storage_mode().unwrap(), StorageMode::Entries);
+    assert_eq!(migrated.open_entries, 2);
+    assert!(participants.is_empty());
+}
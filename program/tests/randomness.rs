@@ -0,0 +1,150 @@
+use solana_program::{
+    clock::Clock,
+    hash::hashv,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    system_instruction,
+};
+use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+use solana_sdk::{signature::Keypair, signer::Signer, transaction::TransactionError};
+
+use lottery::client;
+use lottery::error::LotteryError;
+use lottery::instructions::StartLotteryArgs;
+use lottery::state::{LotteryState, RandomnessMode, SelectionMode, StorageMode};
+
+mod common;
+use common::{lottery_account, lottery_error, oracle_instruction, oracle_message, send, set_time, try_send};
+
+const LOTTERY_ID: u64 = 3;
+const BET: u64 = 1_000_000;
+const REVEAL: [u8; 32] = [7; 32];
+
+struct TestLottery {
+    context: ProgramTestContext,
+    program_id: Pubkey,
+    authority: Keypair,
+    lottery: Pubkey,
+    lottery_end: i64,
+}
+
+/// lamport lottery with a single bet, clock is moved to the end of it, so it can be launched
+async fn start_lottery(commitment: Option<[u8; 32]>, oracle: Option<Pubkey>) -> TestLottery {
+    let program_id = Pubkey::new_unique();
+    let program_test = ProgramTest::new("lottery", program_id, processor!(lottery::processor::process_instruction));
+    let mut context = program_test.start_with_context().await;
+
+    let payer = context.payer.pubkey();
+    let authority = Keypair::new();
+    let participant = Keypair::new();
+    send(&mut context, &[
+        system_instruction::transfer(&payer, &authority.pubkey(), 1_000_000_000),
+        system_instruction::transfer(&payer, &participant.pubkey(), 100_000_000),
+    ], &[]).await;
+
+    let clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+    let lottery_end = clock.unix_timestamp + 100;
+    let lottery = client::get_lottery_address(&program_id, &authority.pubkey(), LOTTERY_ID);
+    let args = StartLotteryArgs {
+        lottery_id: LOTTERY_ID,
+        max_participants: 10,
+        unix_timestamp: clock.unix_timestamp as u64,
+        lottery_end: lottery_end as u64,
+        commitment,
+        reveal_deadline: lottery_end as u64 + 100,
+        oracle,
+        selection_mode: SelectionMode::Uniform,
+        prize_tiers: vec![100],
+        fee_bps: 0,
+        treasury: Pubkey::new_unique(),
+        ticket_price: 0,
+        min_bet: 0,
+        max_bet: 0,
+        mint: None,
+        storage_mode: StorageMode::Slots,
+    };
+
+    send(&mut context, &[client::start_lottery(&program_id, &authority.pubkey(), &lottery, args)], &[&authority]).await;
+    send(&mut context, &[
+        client::donate(&program_id, &participant.pubkey(), &lottery, BET, None, StorageMode::Slots),
+    ], &[&participant]).await;
+
+    context.warp_to_slot(10).unwrap();
+    let mut test_lottery = TestLottery { context, program_id, authority, lottery, lottery_end };
+    set_time(&mut test_lottery.context, lottery_end).await;
+
+    test_lottery
+}
+
+async fn launch(test_lottery: &mut TestLottery, pre_instructions: Vec<Instruction>, mode: RandomnessMode, reveal: Option<[u8; 32]>) -> Result<(), TransactionError> {
+    let mut instructions = pre_instructions;
+    instructions.push(client::launch_lottery(&test_lottery.program_id, &test_lottery.authority.pubkey(), &test_lottery.lottery, mode, reveal));
+
+    let TestLottery { context, authority, .. } = test_lottery;
+    try_send(context, &instructions, &[authority]).await
+}
+
+#[tokio::test]
+async fn commit_reveal_accepts_only_the_committed_seed_before_deadline() {
+    let commitment = hashv(&[&REVEAL]).to_bytes();
+    let mut test_lottery = start_lottery(Some(commitment), None).await;
+
+    let tampered = launch(&mut test_lottery, vec![], RandomnessMode::CommitReveal, Some([8; 32])).await;
+    assert_eq!(tampered.unwrap_err(), lottery_error(0, LotteryError::InvalidReveal));
+
+    let missing = launch(&mut test_lottery, vec![], RandomnessMode::CommitReveal, None).await;
+    assert_eq!(missing.unwrap_err(), lottery_error(0, LotteryError::InvalidReveal));
+
+    launch(&mut test_lottery, vec![], RandomnessMode::CommitReveal, Some(REVEAL)).await.unwrap();
+    let launched = lottery_account(&mut test_lottery.context, &test_lottery.lottery).await;
+    assert_eq!(launched.lottery_state().unwrap(), LotteryState::LAUCNHED);
+    // Slots mode lottery keeps the entropy its winners were drawn from
+    assert_ne!(launched.draw_entropy, [0; 32]);
+}
+
+#[tokio::test]
+async fn commit_reveal_rejects_reveal_after_deadline() {
+    let commitment = hashv(&[&REVEAL]).to_bytes();
+    let mut test_lottery = start_lottery(Some(commitment), None).await;
+
+    let reveal_deadline = test_lottery.lottery_end + 100;
+    set_time(&mut test_lottery.context, reveal_deadline + 1).await;
+
+    let late = launch(&mut test_lottery, vec![], RandomnessMode::CommitReveal, Some(REVEAL)).await;
+    assert_eq!(late.unwrap_err(), lottery_error(0, LotteryError::RevealDeadlinePassed));
+}
+
+#[tokio::test]
+async fn oracle_draw_checks_signer_and_message() {
+    let oracle = Keypair::new();
+    let mut test_lottery = start_lottery(None, Some(oracle.pubkey())).await;
+    let slot = test_lottery.context.banks_client.get_root_slot().await.unwrap();
+    let lottery = test_lottery.lottery;
+    let round = lottery_account(&mut test_lottery.context, &lottery).await.round;
+
+    let stranger = Keypair::new();
+    let wrong_key = launch(&mut test_lottery, vec![oracle_instruction(&stranger, &oracle_message(&lottery, round, slot))], RandomnessMode::Oracle, None).await;
+    assert_eq!(wrong_key.unwrap_err(), lottery_error(1, LotteryError::OracleSignerMismatch));
+
+    // signature for another lottery of the same oracle or for another round of this one isn't accepted
+    let wrong_lottery = launch(&mut test_lottery, vec![oracle_instruction(&oracle, &oracle_message(&Pubkey::new_unique(), round, slot))], RandomnessMode::Oracle, None).await;
+    assert_eq!(wrong_lottery.unwrap_err(), lottery_error(1, LotteryError::InvalidOracleMessage));
+
+    let wrong_round = launch(&mut test_lottery, vec![oracle_instruction(&oracle, &oracle_message(&lottery, round + 1, slot))], RandomnessMode::Oracle, None).await;
+    assert_eq!(wrong_round.unwrap_err(), lottery_error(1, LotteryError::InvalidOracleMessage));
+
+    let future_slot = launch(&mut test_lottery, vec![oracle_instruction(&oracle, &oracle_message(&lottery, round, slot + 1_000))], RandomnessMode::Oracle, None).await;
+    assert_eq!(future_slot.unwrap_err(), lottery_error(1, LotteryError::InvalidOracleMessage));
+
+    let short_message = launch(&mut test_lottery, vec![oracle_instruction(&oracle, &LOTTERY_ID.to_le_bytes())], RandomnessMode::Oracle, None).await;
+    assert_eq!(short_message.unwrap_err(), lottery_error(1, LotteryError::InvalidOracleMessage));
+
+    let unsigned = launch(&mut test_lottery, vec![], RandomnessMode::Oracle, None).await;
+    assert_eq!(unsigned.unwrap_err(), lottery_error(0, LotteryError::InvalidOracleInstruction));
+
+    launch(&mut test_lottery, vec![oracle_instruction(&oracle, &oracle_message(&lottery, round, slot))], RandomnessMode::Oracle, None).await.unwrap();
+    let launched = lottery_account(&mut test_lottery.context, &test_lottery.lottery).await;
+    assert_eq!(launched.lottery_state().unwrap(), LotteryState::LAUCNHED);
+    // Slots mode lottery keeps the entropy its winners were drawn from
+    assert_ne!(launched.draw_entropy, [0; 32]);
+}
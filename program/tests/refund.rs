@@ -0,0 +1,106 @@
+use solana_program::{clock::Clock, pubkey::Pubkey};
+use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+use solana_sdk::{signature::Keypair, signer::Signer};
+
+use lottery::client;
+use lottery::error::LotteryError;
+use lottery::state::{LotteryState, StorageMode};
+
+mod common;
+use common::{balance, fund, lottery_account, lottery_args, lottery_error, send, try_send};
+
+const LOTTERY_ID: u64 = 9;
+const BETS: [u64; 3] = [1_000_000, 2_000_000, 3_000_000];
+
+struct CancelledLottery {
+    context: ProgramTestContext,
+    program_id: Pubkey,
+    authority: Keypair,
+    lottery: Pubkey,
+    participants: Vec<Keypair>,
+}
+
+/// lamport lottery with a bet of every participant, cancelled by its authority
+async fn cancelled_lottery() -> CancelledLottery {
+    let program_id = Pubkey::new_unique();
+    let program_test = ProgramTest::new("lottery", program_id, processor!(lottery::processor::process_instruction));
+    let mut context = program_test.start_with_context().await;
+
+    let authority = Keypair::new();
+    let participants: Vec<Keypair> = BETS.iter().map(|_| Keypair::new()).collect();
+    fund(&mut context, &[&authority], 1_000_000_000).await;
+    fund(&mut context, &participants.iter().collect::<Vec<_>>(), 100_000_000).await;
+
+    let clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+    let lottery = client::get_lottery_address(&program_id, &authority.pubkey(), LOTTERY_ID);
+    let args = lottery_args(LOTTERY_ID, clock.unix_timestamp, 10, StorageMode::Slots);
+    send(&mut context, &[client::start_lottery(&program_id, &authority.pubkey(), &lottery, args)], &[&authority]).await;
+
+    for (participant, bet) in participants.iter().zip(BETS) {
+        send(&mut context, &[
+            client::donate(&program_id, &participant.pubkey(), &lottery, bet, None, StorageMode::Slots),
+        ], &[participant]).await;
+    }
+
+    send(&mut context, &[client::cancel_lottery(&program_id, &authority.pubkey(), &lottery)], &[&authority]).await;
+
+    CancelledLottery { context, program_id, authority, lottery, participants }
+}
+
+#[tokio::test]
+async fn batch_refund_returns_every_bet_once() {
+    let CancelledLottery { mut context, program_id, lottery, participants, .. } = cancelled_lottery().await;
+
+    let participant_keys: Vec<Pubkey> = participants.iter().map(|participant| participant.pubkey()).collect();
+    let mut balances = Vec::new();
+    for participant in participant_keys.iter() {
+        balances.push(balance(&mut context, participant).await);
+    }
+
+    send(&mut context, &[client::refund_participants(&program_id, &lottery, &participant_keys, None, StorageMode::Slots)], &[]).await;
+
+    for ((participant, before), bet) in participant_keys.iter().zip(balances).zip(BETS) {
+        assert_eq!(balance(&mut context, participant).await, before + bet);
+    }
+
+    let refunded = lottery_account(&mut context, &lottery).await;
+    assert_eq!(refunded.participants_count, 0);
+    assert_eq!(refunded.total_lamports, 0);
+    assert_eq!(refunded.lottery_state().unwrap(), LotteryState::CANCELLED);
+
+    // refunded bet is taken out of the lottery, so it can't be paid again
+    let double = try_send(&mut context, &[
+        client::refund_participants(&program_id, &lottery, &participant_keys[..1], None, StorageMode::Slots),
+    ], &[]).await;
+    assert_eq!(double.unwrap_err(), lottery_error(0, LotteryError::InvalidParticipantAccount));
+}
+
+#[tokio::test]
+async fn pending_refunds_block_restart_and_close() {
+    let CancelledLottery { mut context, program_id, authority, lottery, participants } = cancelled_lottery().await;
+
+    let participant_keys: Vec<Pubkey> = participants.iter().map(|participant| participant.pubkey()).collect();
+    send(&mut context, &[client::refund_participants(&program_id, &lottery, &participant_keys[..2], None, StorageMode::Slots)], &[]).await;
+    assert_eq!(lottery_account(&mut context, &lottery).await.participants_count, 1);
+
+    let clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+    let args = lottery_args(LOTTERY_ID, clock.unix_timestamp, 10, StorageMode::Slots);
+    let restart = try_send(&mut context, &[
+        client::start_lottery(&program_id, &authority.pubkey(), &lottery, args),
+    ], &[&authority]).await;
+    assert_eq!(restart.unwrap_err(), lottery_error(0, LotteryError::RefundsPending));
+
+    let close = try_send(&mut context, &[
+        client::close_lottery(&program_id, &authority.pubkey(), &lottery, &authority.pubkey(), None),
+    ], &[&authority]).await;
+    assert_eq!(close.unwrap_err(), lottery_error(0, LotteryError::RefundsPending));
+
+    // the last refund unlocks the lottery
+    send(&mut context, &[client::refund_participants(&program_id, &lottery, &participant_keys[2..], None, StorageMode::Slots)], &[]).await;
+    let args = lottery_args(LOTTERY_ID, clock.unix_timestamp, 10, StorageMode::Slots);
+    send(&mut context, &[client::start_lottery(&program_id, &authority.pubkey(), &lottery, args)], &[&authority]).await;
+
+    let restarted = lottery_account(&mut context, &lottery).await;
+    assert_eq!(restarted.lottery_state().unwrap(), LotteryState::IN_PROGRESS);
+    assert_eq!(restarted.participants_count, 0);
+}
@@ -0,0 +1,157 @@
+use solana_program::{
+    clock::Clock,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    system_instruction,
+};
+use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+use solana_sdk::{signature::Keypair, signer::Signer};
+
+use lottery::client;
+use lottery::instructions::StartLotteryArgs;
+use lottery::state::{LotteryState, RandomnessMode, SelectionMode, StorageMode};
+
+mod common;
+use common::{lottery_account, send};
+
+const LOTTERY_ID: u64 = 7;
+const BET: u64 = 1_000_000;
+const FEE_BPS: u16 = 500;
+
+async fn create_token_account(context: &mut ProgramTestContext, mint: &Pubkey, owner: &Pubkey) -> Pubkey {
+    let token_account = Keypair::new();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let payer = context.payer.pubkey();
+
+    send(context, &[
+        system_instruction::create_account(
+            &payer,
+            &token_account.pubkey(),
+            rent.minimum_balance(spl_token::state::Account::LEN),
+            spl_token::state::Account::LEN as u64,
+            &spl_token::id()
+        ),
+        spl_token::instruction::initialize_account3(&spl_token::id(), &token_account.pubkey(), mint, owner).unwrap(),
+    ], &[&token_account]).await;
+
+    token_account.pubkey()
+}
+
+async fn token_balance(context: &mut ProgramTestContext, token_account: &Pubkey) -> u64 {
+    let account = context.banks_client.get_account(*token_account).await.unwrap().unwrap();
+    spl_token::state::Account::unpack(&account.data).unwrap().amount
+}
+
+#[tokio::test]
+async fn token_lottery_pays_winner_and_treasury_in_tokens() {
+    let program_id = Pubkey::new_unique();
+    let mut program_test = ProgramTest::new("lottery", program_id, processor!(lottery::processor::process_instruction));
+    program_test.add_program("spl_token", spl_token::id(), processor!(spl_token::processor::Processor::process));
+    let mut context = program_test.start_with_context().await;
+
+    let payer = context.payer.pubkey();
+    let authority = Keypair::new();
+    let treasury = Keypair::new();
+    let participants = [Keypair::new(), Keypair::new()];
+    let mint = Keypair::new();
+    let rent = context.banks_client.get_rent().await.unwrap();
+
+    let mut setup = vec![
+        system_instruction::transfer(&payer, &authority.pubkey(), 1_000_000_000),
+        system_instruction::create_account(
+            &payer,
+            &mint.pubkey(),
+            rent.minimum_balance(spl_token::state::Mint::LEN),
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::id()
+        ),
+        spl_token::instruction::initialize_mint2(&spl_token::id(), &mint.pubkey(), &payer, None, 6).unwrap(),
+    ];
+    for participant in participants.iter() {
+        setup.push(system_instruction::transfer(&payer, &participant.pubkey(), 100_000_000));
+    }
+    send(&mut context, &setup, &[&mint]).await;
+
+    let treasury_token = create_token_account(&mut context, &mint.pubkey(), &treasury.pubkey()).await;
+    let authority_token = create_token_account(&mut context, &mint.pubkey(), &authority.pubkey()).await;
+    let mut participant_tokens = Vec::new();
+    for participant in participants.iter() {
+        let token_account = create_token_account(&mut context, &mint.pubkey(), &participant.pubkey()).await;
+        send(&mut context, &[
+            spl_token::instruction::mint_to(&spl_token::id(), &mint.pubkey(), &token_account, &payer, &[], BET).unwrap(),
+        ], &[]).await;
+        participant_tokens.push(token_account);
+    }
+
+    let lottery_pubkey = client::get_lottery_address(&program_id, &authority.pubkey(), LOTTERY_ID);
+    let vault_pubkey = client::get_vault_address(&program_id, &lottery_pubkey);
+
+    let clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+    let lottery_end = clock.unix_timestamp as u64 + 100;
+    let args = StartLotteryArgs {
+        lottery_id: LOTTERY_ID,
+        max_participants: 10,
+        unix_timestamp: clock.unix_timestamp as u64,
+        lottery_end,
+        commitment: None,
+        reveal_deadline: 0,
+        oracle: None,
+        selection_mode: SelectionMode::Uniform,
+        prize_tiers: vec![100],
+        fee_bps: FEE_BPS,
+        treasury: treasury.pubkey(),
+        ticket_price: 0,
+        min_bet: 0,
+        max_bet: 0,
+        mint: Some(mint.pubkey()),
+        storage_mode: StorageMode::Slots,
+    };
+    send(&mut context, &[client::start_lottery(&program_id, &authority.pubkey(), &lottery_pubkey, args)], &[&authority]).await;
+
+    for (participant, token_account) in participants.iter().zip(participant_tokens.iter()) {
+        send(&mut context, &[
+            client::donate(&program_id, &participant.pubkey(), &lottery_pubkey, BET, Some(token_account), StorageMode::Slots),
+        ], &[participant]).await;
+    }
+
+    assert_eq!(token_balance(&mut context, &vault_pubkey).await, 2 * BET);
+    assert_eq!(token_balance(&mut context, &participant_tokens[0]).await, 0);
+
+    // SlotHashes needs a few slots behind and the draw can't happen before the end
+    context.warp_to_slot(10).unwrap();
+    let mut clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = lottery_end as i64;
+    context.set_sysvar(&clock);
+
+    send(&mut context, &[
+        client::launch_lottery(&program_id, &authority.pubkey(), &lottery_pubkey, RandomnessMode::SlotHashes, None),
+    ], &[&authority]).await;
+
+    let launched = lottery_account(&mut context, &lottery_pubkey).await;
+    assert_eq!(launched.lottery_state().unwrap(), LotteryState::LAUCNHED);
+    let winner_index = participants.iter()
+        .position(|participant| participant.pubkey() == launched.winners[0])
+        .unwrap();
+
+    send(&mut context, &[client::complete_lottery(
+        &program_id,
+        &authority.pubkey(),
+        &lottery_pubkey,
+        &treasury_token,
+        &[participant_tokens[winner_index]],
+        true
+    )], &[&authority]).await;
+
+    let fee = 2 * BET * FEE_BPS as u64 / 10_000;
+    assert_eq!(token_balance(&mut context, &treasury_token).await, fee);
+    assert_eq!(token_balance(&mut context, &participant_tokens[winner_index]).await, 2 * BET - fee);
+    assert_eq!(token_balance(&mut context, &vault_pubkey).await, 0);
+    assert_eq!(lottery_account(&mut context, &lottery_pubkey).await.lottery_state().unwrap(), LotteryState::COMPLETED);
+
+    send(&mut context, &[
+        client::close_lottery(&program_id, &authority.pubkey(), &lottery_pubkey, &authority.pubkey(), Some(&authority_token)),
+    ], &[&authority]).await;
+
+    assert!(context.banks_client.get_account(lottery_pubkey).await.unwrap().is_none());
+    assert!(context.banks_client.get_account(vault_pubkey).await.unwrap().is_none());
+}
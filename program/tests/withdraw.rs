@@ -0,0 +1,115 @@
+use solana_program::{clock::Clock, pubkey::Pubkey};
+use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+use solana_sdk::{signature::Keypair, signer::Signer, transaction::TransactionError};
+
+use lottery::client;
+use lottery::error::LotteryError;
+use lottery::instructions::StartLotteryArgs;
+use lottery::state::{LotteryState, StorageMode};
+
+mod common;
+use common::{balance, fund, lottery_account, lottery_args, lottery_error, send, try_send};
+
+const LOTTERY_ID: u64 = 11;
+const TICKET_PRICE: u64 = 1_000_000;
+
+struct TestLottery {
+    context: ProgramTestContext,
+    program_id: Pubkey,
+    lottery: Pubkey,
+    participants: Vec<Keypair>,
+}
+
+/// lamport lottery started with the given args, participants are funded but haven't bet yet
+async fn start_lottery(participants_amount: usize, args: impl FnOnce(i64) -> StartLotteryArgs) -> TestLottery {
+    let program_id = Pubkey::new_unique();
+    let program_test = ProgramTest::new("lottery", program_id, processor!(lottery::processor::process_instruction));
+    let mut context = program_test.start_with_context().await;
+
+    let authority = Keypair::new();
+    let participants: Vec<Keypair> = (0..participants_amount).map(|_| Keypair::new()).collect();
+    fund(&mut context, &[&authority], 1_000_000_000).await;
+    fund(&mut context, &participants.iter().collect::<Vec<_>>(), 100_000_000).await;
+
+    let clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+    let lottery = client::get_lottery_address(&program_id, &authority.pubkey(), LOTTERY_ID);
+    send(&mut context, &[
+        client::start_lottery(&program_id, &authority.pubkey(), &lottery, args(clock.unix_timestamp)),
+    ], &[&authority]).await;
+
+    TestLottery { context, program_id, lottery, participants }
+}
+
+async fn bet(test_lottery: &mut TestLottery, participant: usize, amount: u64) -> Result<(), TransactionError> {
+    let TestLottery { context, program_id, lottery, participants } = test_lottery;
+    let participant = &participants[participant];
+
+    try_send(context, &[
+        client::donate(program_id, &participant.pubkey(), lottery, amount, None, StorageMode::Slots),
+    ], &[participant]).await
+}
+
+async fn withdraw(test_lottery: &mut TestLottery, participant: usize) {
+    let TestLottery { context, program_id, lottery, participants } = test_lottery;
+    let participant = &participants[participant];
+
+    send(context, &[
+        client::withdraw_bet(program_id, &participant.pubkey(), lottery, None, StorageMode::Slots),
+    ], &[participant]).await;
+}
+
+#[tokio::test]
+async fn withdrawal_reopens_full_lottery() {
+    let mut test_lottery = start_lottery(3, |now| lottery_args(LOTTERY_ID, now, 2, StorageMode::Slots)).await;
+
+    bet(&mut test_lottery, 0, 1_000_000).await.unwrap();
+    bet(&mut test_lottery, 1, 2_000_000).await.unwrap();
+    let full = lottery_account(&mut test_lottery.context, &test_lottery.lottery).await;
+    assert_eq!(full.lottery_state().unwrap(), LotteryState::BETS_CLOSED);
+
+    let late = bet(&mut test_lottery, 2, 1_000_000).await;
+    assert_eq!(late.unwrap_err(), lottery_error(0, LotteryError::WrongLifecycleState));
+
+    let participant = test_lottery.participants[0].pubkey();
+    let before = balance(&mut test_lottery.context, &participant).await;
+    withdraw(&mut test_lottery, 0).await;
+    assert_eq!(balance(&mut test_lottery.context, &participant).await, before + 1_000_000);
+
+    let reopened = lottery_account(&mut test_lottery.context, &test_lottery.lottery).await;
+    assert_eq!(reopened.lottery_state().unwrap(), LotteryState::IN_PROGRESS);
+    assert_eq!(reopened.participants_count, 1);
+    assert_eq!(reopened.total_lamports, 2_000_000);
+
+    // freed slot is taken by the next participant
+    bet(&mut test_lottery, 2, 1_000_000).await.unwrap();
+    let full_again = lottery_account(&mut test_lottery.context, &test_lottery.lottery).await;
+    assert_eq!(full_again.lottery_state().unwrap(), LotteryState::BETS_CLOSED);
+}
+
+#[tokio::test]
+async fn ticket_ranges_are_recomputed_after_middle_slot_is_removed() {
+    let mut test_lottery = start_lottery(3, |now| StartLotteryArgs {
+        ticket_price: TICKET_PRICE,
+        ..lottery_args(LOTTERY_ID, now, 10, StorageMode::Slots)
+    }).await;
+
+    for (participant, tickets) in [1, 2, 3].into_iter().enumerate() {
+        bet(&mut test_lottery, participant, tickets * TICKET_PRICE).await.unwrap();
+    }
+
+    let account = test_lottery.context.banks_client.get_account(test_lottery.lottery).await.unwrap().unwrap();
+    let (_, slots) = client::decode_lottery(&account.data).unwrap();
+    assert_eq!(slots.iter().map(|slot| slot.first_ticket).collect::<Vec<_>>(), vec![0, slots[0].tickets, slots[0].tickets + slots[1].tickets]);
+
+    // slots are sorted by pubkey, the middle one belongs to whoever sorts second
+    let middle = test_lottery.participants.iter().position(|participant| participant.pubkey() == slots[1].pubkey).unwrap();
+    withdraw(&mut test_lottery, middle).await;
+
+    let account = test_lottery.context.banks_client.get_account(test_lottery.lottery).await.unwrap().unwrap();
+    let (header, remaining) = client::decode_lottery(&account.data).unwrap();
+    assert_eq!(remaining.iter().map(|slot| slot.pubkey).collect::<Vec<_>>(), vec![slots[0].pubkey, slots[2].pubkey]);
+    assert_eq!(remaining[0].first_ticket, 0);
+    assert_eq!(remaining[1].first_ticket, slots[0].tickets);
+    assert_eq!(header.total_tickets, slots[0].tickets + slots[2].tickets);
+    assert_eq!(header.total_lamports, slots[0].lamports + slots[2].lamports);
+}
@@ -1,6 +1,33 @@
 import {serialize} from 'borsh';
+import { PublicKey } from '@solana/web3.js';
 import moment from 'moment';
 
+/**
+ * Codes of lottery program instructions, order matches LotteryInstructions enum
+ */
+export enum InstructionCode {
+  StartLottery,
+  Donate,
+  LaunchLottery,
+  CompleteLottery,
+}
+
+/**
+ * Who wins, matches SelectionMode enum
+ */
+export enum SelectionMode {
+  Uniform,
+  Weighted,
+}
+
+/**
+ * Where bets are kept, matches StorageMode enum
+ */
+export enum StorageMode {
+  Slots,
+  Entries,
+}
+
 class Instruction {
     code: number;
 
@@ -13,16 +40,63 @@ class Instruction {
     ]);
 }
 
+export interface StartLotteryArgs {
+  lottery_id: number;
+  max_participants: number;
+  lottery_end: number;
+  treasury: PublicKey;
+  prize_tiers?: number[];
+  fee_bps?: number;
+  selection_mode?: SelectionMode;
+  storage_mode?: StorageMode;
+  commitment?: Uint8Array;
+  reveal_deadline?: number;
+  oracle?: PublicKey;
+  ticket_price?: number;
+  min_bet?: number;
+  max_bet?: number;
+  mint?: PublicKey;
+}
+
 class StartLotteryInstruction extends Instruction {
+  lottery_id: number;
   max_participants: number;
   unix_timestamp: number;
-
-  constructor(max_participants: number) {
-    super(0);
-    this.max_participants = max_participants;
+  lottery_end: number;
+  commitment: Uint8Array | null;
+  reveal_deadline: number;
+  oracle: Uint8Array | null;
+  selection_mode: number;
+  prize_tiers: number[];
+  fee_bps: number;
+  treasury: Uint8Array;
+  ticket_price: number;
+  min_bet: number;
+  max_bet: number;
+  mint: Uint8Array | null;
+  storage_mode: number;
+
+  constructor(args: StartLotteryArgs) {
+    super(InstructionCode.StartLottery);
+    this.lottery_id = args.lottery_id;
+    this.max_participants = args.max_participants;
     this.unix_timestamp = moment().unix();
+    this.lottery_end = args.lottery_end;
+    this.commitment = args.commitment || null;
+    this.reveal_deadline = args.reveal_deadline || 0;
+    this.oracle = args.oracle ? args.oracle.toBytes() : null;
+    this.selection_mode = args.selection_mode || SelectionMode.Uniform;
+    this.prize_tiers = args.prize_tiers || [100];
+    this.fee_bps = args.fee_bps || 0;
+    this.treasury = args.treasury.toBytes();
+    this.ticket_price = args.ticket_price || 0;
+    this.min_bet = args.min_bet || 0;
+    this.max_bet = args.max_bet || 0;
+    this.mint = args.mint ? args.mint.toBytes() : null;
+    this.storage_mode = args.storage_mode || StorageMode.Slots;
   }
 
+  // code followed by borsh serialized StartLotteryArgs, enums are serialized as u8
   static startLotterySerializationSchema = new Map([
     [
       StartLotteryInstruction,
@@ -30,30 +104,22 @@ class StartLotteryInstruction extends Instruction {
         kind: 'struct',
         fields: [
           ['code', 'u8'],
+          ['lottery_id', 'u64'],
           ['max_participants', 'u32'],
-          ['unix_timestamp', 'u64']
-        ]
-      }
-    ]
-  ]);
-}
-
-class LaunchLotteryInstruction extends Instruction {
-  hashes: string;
-
-  constructor(hashes: string) {
-    super(2);
-    this.hashes = hashes;
-  }
-
-  static launchLotteryInstructionSchema = new Map([
-    [
-      LaunchLotteryInstruction, 
-      {
-        kind: 'struct', 
-        fields: [
-          ['code', 'u8'],
-          ['hashes', 'string']
+          ['unix_timestamp', 'u64'],
+          ['lottery_end', 'u64'],
+          ['commitment', {kind: 'option', type: [32]}],
+          ['reveal_deadline', 'u64'],
+          ['oracle', {kind: 'option', type: [32]}],
+          ['selection_mode', 'u8'],
+          ['prize_tiers', ['u8']],
+          ['fee_bps', 'u16'],
+          ['treasury', [32]],
+          ['ticket_price', 'u64'],
+          ['min_bet', 'u64'],
+          ['max_bet', 'u64'],
+          ['mint', {kind: 'option', type: [32]}],
+          ['storage_mode', 'u8']
         ]
       }
     ]
@@ -64,7 +130,7 @@ class DonateInstruction extends Instruction {
   amount: number;
 
   constructor(amount: number) {
-    super(1);
+    super(InstructionCode.Donate);
     this.amount = amount;
   }
 
@@ -84,26 +150,24 @@ class DonateInstruction extends Instruction {
 
 /**
  * Creating Launch Lottery Instruction
- * @param hashes - list with recent blockhashes
+ * winner is drawn on-chain from SlotHashes sysvar, so no hashes are passed anymore
+ * @param reveal - operator's 32 bytes secret seed, only for lotteries started with a commitment
  * @returns - buffered transaction data
  */
-export function createLaunchLotteryInstruction(hashes: string | string[]): Buffer {
-  if (typeof hashes !== 'string') {
-    hashes = hashes.join('');
-  }
-  console.log(`result hash string is ${hashes}`)
-  const launchLotteryInstruction = new LaunchLotteryInstruction(hashes);
-  const serialized = serialize(LaunchLotteryInstruction.launchLotteryInstructionSchema, launchLotteryInstruction);
-  return Buffer.from(serialized);
+export function createLaunchLotteryInstruction(reveal?: Uint8Array): Buffer {
+  const launchInstruction = new Instruction(InstructionCode.LaunchLottery);
+  const serialized = serialize(Instruction.instructionSchema, launchInstruction);
+  // reveal is appended as raw bytes, not as borsh option
+  return reveal ? Buffer.concat([Buffer.from(serialized), Buffer.from(reveal)]) : Buffer.from(serialized);
 }
 
 /**
  * Creating Start Lottery Instruction
- * @param max_participants - max amount of lottery participants
+ * @param args - lottery id, limits and payout settings, see StartLotteryArgs of the program
  * @returns - buffered transaction data
  */
-export function createStartLotteryInstruction(max_participants: number): Buffer {
-  const startLotteryInstruction = new StartLotteryInstruction(max_participants);
+export function createStartLotteryInstruction(args: StartLotteryArgs): Buffer {
+  const startLotteryInstruction = new StartLotteryInstruction(args);
   const serialized = serialize(StartLotteryInstruction.startLotterySerializationSchema, startLotteryInstruction);
   return Buffer.from(serialized);
 }
@@ -124,7 +188,7 @@ export function createDonateInstruction(amount: number): Buffer {
  * @returns buffered transaction data
  */
 export function createCompleteLotteryInstruction(): Buffer {
-  const completeInstruction = new Instruction(3);
+  const completeInstruction = new Instruction(InstructionCode.CompleteLottery);
   const serialized = serialize(Instruction.instructionSchema, completeInstruction);
   return Buffer.from(serialized);
-}
\ No newline at end of file
+}
@@ -8,24 +8,23 @@ import {
     sendAndConfirmTransaction,
     LAMPORTS_PER_SOL,
     SYSVAR_RENT_PUBKEY,
+    SYSVAR_SLOT_HASHES_PUBKEY,
 } from '@solana/web3.js';
 import path from 'path';
 import os from 'os';
-import { getPayer, getRpcUrl, getSizeOfAccount, writeLogs, createKeypairFromFile, getConfig, startFetchingLastBlockHashes } from './utils';
-import {deserializeUnchecked} from 'borsh';
+import { getPayer, getRpcUrl, getSizeOfAccount, writeLogs, createKeypairFromFile, getConfig, getLotteryPubkey } from './utils';
 import { fs } from 'mz';
-import { LotteryState, LotteryStruct, serializingSchema } from './state';
+import { LotteryState, LotteryStruct, decodeLotteryAccount, getWinners } from './state';
 import { BorshError } from 'borsh';
 import moment from 'moment';
 import { createCompleteLotteryInstruction, createDonateInstruction, createLaunchLotteryInstruction, createStartLotteryInstruction } from './instructions';
-import {BN} from 'bn.js';
 import { clearInterval } from 'timers';
 import {eventEmitter} from './eventsHandler';
 
 interface AccountConfig {
+  lottery_id: number;
   max_participants: number;
   lottery_duration: number;
-  blockhashes_num: number;
 }
 
 class LifeCycle {
@@ -45,14 +44,8 @@ class LifeCycle {
     );
     public CONFIG_FILE_PATH = path.resolve(this.CONFIG_DIRECTORY_PATH, 'config.yml');
     private LOTTERY_CONFIG_PATH = path.resolve(this.CONFIG_DIRECTORY_PATH, 'lottery', 'config.yml');
-    private LOTTERY_SEED = "lottery";
     private accountSize: number | null = null;
     private accountConfig: AccountConfig | null = null;
-    private DEFAULT_WINNER_PUBLICKEY = Uint8Array.from(Array(32).fill(0));
-
-    constructor() {
-      this.asyncLotteryCallback.bind(this);
-    }
 
     /**
      * Establishing connection with RPC cluster
@@ -80,7 +73,7 @@ class LifeCycle {
         this.accountConfig = await (getConfig(this.LOTTERY_CONFIG_PATH)) as unknown as AccountConfig;
         console.log('account config')
       } catch(e) {
-        throw new Error('Invalid config for lottery account! Make sure config have following fields:\nmax_participants of type number\nlottery_duration of type number\nblockhashes_num of type number');
+        throw new Error('Invalid config for lottery account! Make sure config have following fields:\nlottery_id of type number\nmax_participants of type number\nlottery_duration of type number');
       }
 
       this.accountSize = await getSizeOfAccount(this.LOTTERY_CONFIG_PATH);
@@ -135,8 +128,8 @@ class LifeCycle {
       }
       // writeLogs(`Using program ${this.programId.toBase58()}`);
 
-      // Searching valid PDA key from lottery account
-      this.lotteryPubkey = (await PublicKey.findProgramAddress([Buffer.from(this.LOTTERY_SEED, 'utf-8')], this.programId))[0];
+      // Lottery PDA is derived from its creator and lottery id, so one program hosts many lotteries
+      this.lotteryPubkey = await getLotteryPubkey(this.programId, this.payer.publicKey, this.accountConfig.lottery_id || 0);
     
       // Checking whether PDA account was created earlier
       const lotteryAccount = await this.connection.getAccountInfo(this.lotteryPubkey);
@@ -149,10 +142,9 @@ class LifeCycle {
 
         if (lotteryAccount) {
           console.log('lottery account is:', lotteryAccount.lottery_state);
-          console.log('default winner', new PublicKey(this.DEFAULT_WINNER_PUBLICKEY).toBase58());
-          console.log('lottery winner', new PublicKey(lotteryAccount.winner).toBase58());
-          console.log('equals:', new PublicKey(lotteryAccount.winner).toBase58() === new PublicKey(this.DEFAULT_WINNER_PUBLICKEY).toBase58());
-          if (lotteryAccount.lottery_state === LotteryState.COMPLETED || new PublicKey(lotteryAccount.winner).toBase58() !== new PublicKey(this.DEFAULT_WINNER_PUBLICKEY).toBase58()) {
+          console.log('lottery winners', getWinners(lotteryAccount).map((winner) => winner.toBase58()));
+          // lottery can be started again only after its pot is paid out
+          if (lotteryAccount.lottery_state === LotteryState.COMPLETED) {
             // we have to start lottery all over again
             writeLogs('Starting lottery again...');
             return await this.startLottery();
@@ -173,9 +165,8 @@ class LifeCycle {
         if (!bytes) {
           throw new Error(`Unable to fetch account info for ${this.lotteryPubkey} public key`);
         }
-        // unchecked deserialization, because map structure can have various length
-        const lotteryAccount = deserializeUnchecked(serializingSchema, LotteryStruct, bytes.data);
-        return lotteryAccount;
+        // participant slots after the header aren't needed here
+        return decodeLotteryAccount(bytes.data).header;
       } catch (e) {
         if (e instanceof BorshError) {
           throw new Error(`Error with deserializing struct! ${e}`);
@@ -186,15 +177,15 @@ class LifeCycle {
     }
 
     /**
-     * Checking whether lottery reached max amount of participants or its time is up
+     * Checking whether lottery has ended, so its draw can be launched
      */
     async checkTimeOrParticipants(): Promise<void> {
       const lotteryAccount = await this.getLotteryAccount();
 
-      const isMaxAmountOfParticipantas = lotteryAccount.participants.size >= lotteryAccount.max_participants;
-      const isTimeUp = new BN(lotteryAccount.lottery_start).toNumber() + this.accountConfig.lottery_duration <= moment().unix();
+      // bets can be placed until the end even when the lottery is full, because participants can withdraw
+      const isTimeUp = lotteryAccount.lottery_end.toNumber() <= moment().unix();
 
-      if (isMaxAmountOfParticipantas || isTimeUp) {
+      if (isTimeUp) {
         // we have to launch loterry
         return Promise.resolve();
       } else {
@@ -203,76 +194,62 @@ class LifeCycle {
     }
 
     /**
-     * Event listener, emits after Nth amount of blockhashes recieved
-     * @param eventObject - event object
+     * Launching process of choosing winner of lottery
+     * winners are drawn on-chain from SlotHashes sysvar
      */
-    private asyncLotteryCallback(eventObject: {blockhashes: string[]}): void {
-      console.log("fetched blockhashes are", eventObject.blockhashes);
+    async launchLottery(): Promise<void> {
+      const lotteryAccount = await this.getLotteryAccount();
+
+      if (lotteryAccount.lottery_state === LotteryState.LAUCNHED) {
+        return this.completeLottery({winners: getWinners(lotteryAccount)});
+      }
 
-      const instructionData = createLaunchLotteryInstruction(eventObject.blockhashes);
       const instruction = new TransactionInstruction({
         keys: [
           {pubkey: this.payer.publicKey, isSigner: true, isWritable: false},
-          {pubkey: this.lotteryPubkey, isSigner: false, isWritable: true}
+          {pubkey: this.lotteryPubkey, isSigner: false, isWritable: true},
+          {pubkey: SYSVAR_SLOT_HASHES_PUBKEY, isSigner: false, isWritable: false},
         ],
         programId: this.programId,
-        data: instructionData,
+        data: createLaunchLotteryInstruction(),
       });
 
       console.log("Executing launch lottery instruction, data is", instruction);
 
-      sendAndConfirmTransaction(this.connection, new Transaction().add(instruction), [this.payer], {commitment: "confirmed"})
-        .then(() => {
-          // Start checking if winner was chosen
-          const intervalID = setInterval(async () => {
-            const lotteryAccount = await this.getLotteryAccount();
-            console.log('lotteryAccount', lotteryAccount);
-
-            if (lotteryAccount.winner !== this.DEFAULT_WINNER_PUBLICKEY && lotteryAccount.lottery_state === LotteryState.LAUCNHED) {
-              clearInterval(intervalID);
-              const winnerPubkey = new PublicKey(lotteryAccount.winner);
-              // firing complete lottery event
-              eventEmitter.emitCompleteLotteryEvent({winner: winnerPubkey});
-            }
-          }, 1000);
-
-          // listen for complete lottery event to be fired
-          eventEmitter.onCompleteLotteryEvent(this.completeLottery.bind(this), intervalID);
-      }); 
-
+      await sendAndConfirmTransaction(this.connection, new Transaction().add(instruction), [this.payer], {commitment: "confirmed"});
       console.log("Launch lottery was executed");
-    }
 
-    /**
-     * Launching process of choosing winner of lottery
-     */
-    async launchLottery(): Promise<void> {
-      const lotteryAccount = await this.getLotteryAccount();
+      // Start checking if winners were chosen
+      const intervalID = setInterval(async () => {
+        const lotteryAccount = await this.getLotteryAccount();
+        console.log('lotteryAccount', lotteryAccount);
 
-      if (lotteryAccount.lottery_state === LotteryState.LAUCNHED) {
-        this.completeLottery({winner: new PublicKey(lotteryAccount.winner)});
-      } else {
-        console.log('amount of blockhashes required:', this.accountConfig.blockhashes_num);
-        const intervalID = startFetchingLastBlockHashes(this.connection, this.accountConfig.blockhashes_num);
-  
-        // listen for blockhashes event to be fired
-        eventEmitter.onBlockHashesEvent(this.asyncLotteryCallback.bind(this), intervalID);
-      }
+        const winners = getWinners(lotteryAccount);
+        if (winners.length === lotteryAccount.winners_amount && lotteryAccount.lottery_state === LotteryState.LAUCNHED) {
+          clearInterval(intervalID);
+          // firing complete lottery event
+          eventEmitter.emitCompleteLotteryEvent({winners});
+        }
+      }, 1000);
+
+      // listen for complete lottery event to be fired
+      eventEmitter.onCompleteLotteryEvent(this.completeLottery.bind(this), intervalID);
     }
 
     /**
-     * Finishing lottery and transfer lamports from PDA account to winner account
-     * @param lotteryWinner - Public key of lottery winner
+     * Finishing lottery and transfer lamports from PDA account to winner accounts and fee to treasury
+     * @param eventObject - Public keys of lottery winners in tier order
      */
-    private completeLottery(eventObject: {winner: PublicKey}): void {
-      console.log('winner pubkey is', eventObject.winner.toBase58());
+    private completeLottery(eventObject: {winners: PublicKey[]}): void {
+      console.log('winner pubkeys are', eventObject.winners.map((winner) => winner.toBase58()));
       const instructionData = createCompleteLotteryInstruction();
       const instruction = new TransactionInstruction({
         keys: [
           {pubkey: this.payer.publicKey, isSigner: true, isWritable: false},
           {pubkey: this.lotteryPubkey, isSigner: false, isWritable: true},
-          {pubkey: eventObject.winner, isSigner: false, isWritable: true},
-          {pubkey: SystemProgram.programId, isSigner: false, isWritable: false},
+          // payer is the treasury of lotteries started by this script
+          {pubkey: this.payer.publicKey, isSigner: false, isWritable: true},
+          ...eventObject.winners.map((winner) => ({pubkey: winner, isSigner: false, isWritable: true})),
         ],
         programId: this.programId,
         data: instructionData,
@@ -295,11 +272,17 @@ class LifeCycle {
      * Initialiaing Lottery Account
      */
     private async startLottery(): Promise<void> {
-      const instructionData = createStartLotteryInstruction(this.accountConfig.max_participants);
+      const instructionData = createStartLotteryInstruction({
+        lottery_id: this.accountConfig.lottery_id || 0,
+        max_participants: this.accountConfig.max_participants,
+        lottery_end: moment().unix() + this.accountConfig.lottery_duration,
+        treasury: this.payer.publicKey,
+      });
       const instruction = new TransactionInstruction({
         programId: this.programId,
         keys: [
-          {pubkey: this.payer.publicKey, isSigner: true, isWritable: false},
+          // authority pays rent of the lottery account
+          {pubkey: this.payer.publicKey, isSigner: true, isWritable: true},
           {pubkey: this.lotteryPubkey, isSigner: false, isWritable: true},
           {pubkey: SYSVAR_RENT_PUBKEY, isSigner: false, isWritable: false},
           {pubkey: SystemProgram.programId, isSigner: false, isWritable: false},
@@ -1,95 +1,170 @@
 import { PublicKey } from "@solana/web3.js";
-import {deserialize, serialize} from 'borsh';
-import moment from "moment";
-import path from "path";
-import os from 'os';
-import { getConfig } from "./utils";
+import {deserializeUnchecked} from 'borsh';
+import BN from 'bn.js';
 
 export enum LotteryState {
     BETS_CLOSED,
     IN_PROGRESS,
-    LAUCNHED, 
-    COMPLETED
+    LAUCNHED,
+    COMPLETED,
+    CANCELLED
 }
 
-interface AccountConfig {
-  max_participants: number;
-  lottery_duration: number;
-  blockhases_num: number;
-}
+/** lottery account starts with these bytes, followed by layout version */
+export const LOTTERY_DISCRIMINATOR = Buffer.from('LOTTERY', 'utf-8');
+export const LOTTERY_VERSION = 2;
+/** fixed part of lottery account, participant slots go right after it */
+export const LOTTERY_HEADER_SIZE = 672;
+export const PARTICIPANT_SLOT_SIZE = 56;
+export const MAX_WINNERS = 10;
 
-export interface LotteryStructInterface {
+/**
+ * Header of lottery account, fields go in the same order as in LotteryAccount of the program
+ * header has no padding and all its fields are little endian, so it's read with a borsh struct schema
+ */
+export class LotteryStruct {
+    discriminator: Uint8Array;
+    version: number;
+    winners: Uint8Array[];
+    authority: Uint8Array;
+    treasury: Uint8Array;
+    pending_authority: Uint8Array;
+    oracle: Uint8Array;
+    mint: Uint8Array;
+    commitment: Uint8Array;
+    draw_entropy: Uint8Array;
+    lottery_start: BN;
+    lottery_end: BN;
+    lottery_id: BN;
+    reveal_deadline: BN;
+    ticket_price: BN;
+    min_bet: BN;
+    max_bet: BN;
+    total_tickets: BN;
+    round: BN;
+    total_weight: BN;
+    total_lamports: BN;
     max_participants: number;
+    participants_count: number;
+    fee_bps: number;
     lottery_state: LotteryState;
-    winner: Uint8Array;
-    lottery_start: number;
-    participants: Map<Uint8Array, number>
+    randomness_mode: number;
+    selection_mode: number;
+    winners_amount: number;
+    prize_tiers: Uint8Array;
+    storage_mode: number;
+    reserved: Uint8Array;
+    open_entries: number;
+
+    constructor(fields: Partial<LotteryStruct>) {
+        Object.assign(this, fields);
+    }
 }
 
-export class LotteryStruct {
-    max_participants: number;
-    lottery_state: LotteryState;
-    winner: Uint8Array;
-    lottery_start: number;
-    participants: Map<Uint8Array, number>
+/**
+ * Participant slot, used slots are sorted by pubkey
+ */
+export class ParticipantBet {
+    pubkey: Uint8Array;
+    lamports: BN;
+    tickets: BN;
+    first_ticket: BN;
 
-    constructor({ 
-          max_participants, 
-          lottery_state, 
-          winner,
-          lottery_start,
-          participants, }: LotteryStructInterface) {
-        this.max_participants = max_participants;
-        this.lottery_state = lottery_state;
-        this.winner = winner;
-        this.lottery_start = lottery_start;
-        this.participants = participants;
+    constructor(fields: Partial<ParticipantBet>) {
+        Object.assign(this, fields);
     }
 }
 
-export const serializingSchema = new Map([
+export const serializingSchema = new Map<any, any>([
   [
     LotteryStruct,
     {
       kind: "struct",
       fields: [
+        ["discriminator", [7]], // 7 bytes
+        ["version", "u8"], // 1 byte
+        ["winners", [[32], MAX_WINNERS]], // 32 bytes * MAX_WINNERS
+        ["authority", [32]], // 32 bytes
+        ["treasury", [32]], // 32 bytes
+        ["pending_authority", [32]], // 32 bytes
+        ["oracle", [32]], // 32 bytes
+        ["mint", [32]], // 32 bytes
+        ["commitment", [32]], // 32 bytes
+        ["draw_entropy", [32]], // 32 bytes
+        ["lottery_start", "u64"], // 8 bytes
+        ["lottery_end", "u64"], // 8 bytes
+        ["lottery_id", "u64"], // 8 bytes
+        ["reveal_deadline", "u64"], // 8 bytes
+        ["ticket_price", "u64"], // 8 bytes
+        ["min_bet", "u64"], // 8 bytes
+        ["max_bet", "u64"], // 8 bytes
+        ["total_tickets", "u64"], // 8 bytes
+        ["round", "u64"], // 8 bytes
+        ["total_weight", "u64"], // 8 bytes
+        ["total_lamports", "u64"], // 8 bytes
         ["max_participants", "u32"], // 4 bytes
+        ["participants_count", "u32"], // 4 bytes
+        ["fee_bps", "u16"], // 2 bytes
         ["lottery_state", "u8"], // 1 byte
-        ["winner", ['u8', 32]], // 32 bytes
-        ["lottery_start", "u64"], // 8 bytes
-        ["participants", {kind: "map", key: ['u8', 32], value: "u64"}], // 40 bytes per entry(key:value) + 4 bytes capacity
-        // everything except participants map takes 45 bytes in total
+        ["randomness_mode", "u8"], // 1 byte
+        ["selection_mode", "u8"], // 1 byte
+        ["winners_amount", "u8"], // 1 byte
+        ["prize_tiers", [MAX_WINNERS]], // MAX_WINNERS bytes
+        ["storage_mode", "u8"], // 1 byte
+        ["reserved", [3]], // 3 bytes
+        ["open_entries", "u32"], // 4 bytes
+        // total - 672 bytes
+      ]
+    }
+  ],
+  [
+    ParticipantBet,
+    {
+      kind: "struct",
+      fields: [
+        ["pubkey", [32]], // 32 bytes
+        ["lamports", "u64"], // 8 bytes
+        ["tickets", "u64"], // 8 bytes
+        ["first_ticket", "u64"], // 8 bytes
+        // total - 56 bytes per slot
       ]
     }
   ]
 ]);
 
-// only for test purposes
-export async function testSerialization(): Promise<Uint8Array> {
-  const config = await getConfig(path.resolve(
-    os.homedir(),
-    '.config', 
-    'solana', 
-    'cli',
-    'lottery', 
-    'config.yml'
-  )) as unknown as AccountConfig;
+/**
+ * Decoding lottery account data
+ * @param data - raw account data
+ * @returns header and participants, who have already donated, slots after them are ignored
+ */
+export function decodeLotteryAccount(data: Buffer): {header: LotteryStruct, participants: ParticipantBet[]} {
+  if (data.length < LOTTERY_HEADER_SIZE || !data.subarray(0, LOTTERY_DISCRIMINATOR.length).equals(LOTTERY_DISCRIMINATOR)) {
+    throw new Error('Account is not a lottery account or has to be migrated with MigrateLottery');
+  }
 
-  const testMap: Map<Uint8Array, number> = new Map();  
-  for (let i = 0; i < config.max_participants; i++) {
-    testMap.set(new PublicKey(i * i).toBytes(), i);
+  const header = deserializeUnchecked(serializingSchema, LotteryStruct, data.subarray(0, LOTTERY_HEADER_SIZE));
+  if (header.version !== LOTTERY_VERSION) {
+    throw new Error(`Unsupported lottery account version ${header.version}`);
   }
 
-  const testLotteryStruct = new LotteryStruct({
-                                  participants: testMap, 
-                                  max_participants: config.max_participants, 
-                                  lottery_state: LotteryState.IN_PROGRESS,
-                                  winner: new PublicKey(12).toBytes(),
-                                  lottery_start: moment().unix()
-                                });
-  const buffer = serialize(serializingSchema, testLotteryStruct);
-  console.log(buffer.byteLength);
-	const deserializedStruct = deserialize<LotteryStruct>(serializingSchema, LotteryStruct, Buffer.from(buffer));
-  console.log('deserialized', deserializedStruct);
-  return buffer;
-}
\ No newline at end of file
+  // participants of a lottery in Entries mode are kept in their own accounts
+  const participants: ParticipantBet[] = [];
+  if (header.storage_mode === 0) {
+    for (let i = 0; i < header.participants_count; i++) {
+      const offset = LOTTERY_HEADER_SIZE + i * PARTICIPANT_SLOT_SIZE;
+      participants.push(deserializeUnchecked(serializingSchema, ParticipantBet, data.subarray(offset, offset + PARTICIPANT_SLOT_SIZE)));
+    }
+  }
+
+  return {header, participants};
+}
+
+/**
+ * Winners in tier order, tiers without a winner are skipped
+ */
+export function getWinners(header: LotteryStruct): PublicKey[] {
+  return header.winners
+    .slice(0, header.winners_amount)
+    .map((winner) => new PublicKey(winner))
+    .filter((winner) => !winner.equals(PublicKey.default));
+}
@@ -1,10 +1,11 @@
 import moment from 'moment';
 import { fs } from 'mz';
 import yaml from 'yaml';
-import { Commitment, Connection, Keypair, PublicKey } from '@solana/web3.js';
-import { LotteryState, LotteryStruct, serializingSchema } from './state';
-import {serialize} from 'borsh';
-import {eventEmitter} from './eventsHandler';
+import { Keypair, PublicKey } from '@solana/web3.js';
+import BN from 'bn.js';
+import { LOTTERY_HEADER_SIZE, PARTICIPANT_SLOT_SIZE } from './state';
+
+const LOTTERY_SEED = "lottery";
 
 /**
  * Logging Errors and any logic of script
@@ -76,53 +77,30 @@ export async function getPayer(CONFIG_FILE_PATH: string): Promise<Keypair> {
 }
 
 /**
- * Calculate size of an account to be rent exempt
+ * Calculate size of an account to be rent exempt, fixed header and a slot per participant
  * @param CONFIG_FILE_PATH - path to lottery config file
  * @returns size of an account to create
  */
 export async function getSizeOfAccount(CONFIG_FILE_PATH: string): Promise<number> {
     const lottery_config = await getConfig(CONFIG_FILE_PATH);
-    let max_participants = lottery_config.max_participants || 10;
-    let testMap: Map<Uint8Array, number> = new Map();
-
-    for (let i = 0; i < max_participants; i++) {
-        testMap.set(new PublicKey(i).toBytes(), i);
-    }
-    const testLotteryStruct = new LotteryStruct({
-        participants: testMap, 
-        max_participants, 
-        lottery_state: LotteryState.IN_PROGRESS,
-        winner: new PublicKey(42).toBytes(),
-        lottery_start: moment().unix()
-    });
+    const max_participants = lottery_config.max_participants || 10;
 
-    const serializedStruct = serialize(serializingSchema, testLotteryStruct);
-    return serializedStruct.byteLength;
+    return LOTTERY_HEADER_SIZE + max_participants * PARTICIPANT_SLOT_SIZE;
 }
 
 /**
- * Getting N last blockhashes from solana network
- * @param connection - connection to RPC cluster
- * @param blockhases_num - number of blockhashes to collect
- * @returns array of blockhashes
+ * Every lottery has its own PDA, derived from the authority, who created it, and lottery id
+ * @param programId - lottery program
+ * @param authority - creator of the lottery
+ * @param lotteryId - id of the lottery, unique per authority
+ * @returns lottery PDA
  */
-export function startFetchingLastBlockHashes(connection: Connection, blockhases_num: number): NodeJS.Timeout {
-    const blockType: Commitment = 'finalized';
-    let hashes: string[] = [];
-
-    const intervalID = setInterval(async () => {
-        if (hashes.length >= blockhases_num) {
-            clearInterval(intervalID);
-            // firing blockhashes event
-            eventEmitter.emitBlockHashesEvent({blockhashes: hashes});
-        }
-        let hash = await connection.getLatestBlockhash(blockType);
-        console.log('fetched hash is:', hash.blockhash, 'hashes length', hashes.length);
-
-        if (!hashes.includes(hash.blockhash)) {
-            hashes.push(hash.blockhash);
-        }
-    }, 1000);
+export async function getLotteryPubkey(programId: PublicKey, authority: PublicKey, lotteryId: number): Promise<PublicKey> {
+    const lotteryIdBytes = new BN(lotteryId).toArrayLike(Buffer, 'le', 8);
+    const [lotteryPubkey] = await PublicKey.findProgramAddress(
+        [Buffer.from(LOTTERY_SEED, 'utf-8'), authority.toBuffer(), lotteryIdBytes],
+        programId
+    );
 
-    return intervalID;
+    return lotteryPubkey;
 }